@@ -1,10 +1,15 @@
 import json
 import logging
+import os
 import unittest
 
 import requests
 
 
+# a key with the `work:read` and `work:write` scopes, see `make http-integration-test`
+AUTH = {"Authorization": f"Bearer {os.environ.get('PP_API_KEY', '')}"}
+
+
 class WorkAPITests(unittest.TestCase):
     @classmethod
     def setUpClass(cls):
@@ -17,12 +22,22 @@ class WorkAPITests(unittest.TestCase):
     def setUp(self):
         print("\n")
 
-    # `curl -i -X POST localhost:3000/work`
+    # `curl -i -X GET localhost:3000/healthz`
+    def test_health_checks(self):
+        # when
+        alive = requests.get("http://localhost:3000/healthz")
+        ready = requests.get("http://localhost:3000/readyz")
+        # then
+        self.assertEqual(alive.status_code, 200)
+        self.assertEqual(ready.status_code, 200)
+        self.assertEqual(json.loads(ready.text)["checks"]["postgres"], {"status": "up"})
+
+    # `curl -i -X POST localhost:3000/work -d '{"add_up_to": 10}'`
     def test_create_work(self):
         # given
         url = "http://localhost:3000/work"
         # when
-        res = requests.post(url)
+        res = requests.post(url, json={"add_up_to": 10}, headers=AUTH)
         # then
         self.assertEqual(res.status_code, 200)
         WorkAPITests.new_work = json.loads(res.text)
@@ -33,7 +48,7 @@ class WorkAPITests(unittest.TestCase):
         # given
         url = f"http://localhost:3000/work/{WorkAPITests.new_work['id']}"
         # when
-        res = requests.get(url)
+        res = requests.get(url, headers=AUTH)
         # then
         self.assertEqual(res.status_code, 200)
         self.assertDictEqual(WorkAPITests.new_work, json.loads(res.text))
@@ -44,16 +59,64 @@ class WorkAPITests(unittest.TestCase):
         # given
         url = f"http://localhost:3000/work/search?work_code={WorkAPITests.new_work['work_code']}"
         # when
-        res = requests.get(url)
+        res = requests.get(url, headers=AUTH)
         # then
         self.assertEqual(res.status_code, 200)
-        work_list = json.loads(res.text)
+        page = json.loads(res.text)
+        self.assertIsNone(page["next_cursor"])
+        work_list = page["items"]
         self.assertEqual(len(work_list), 1)
         self.assertDictEqual(WorkAPITests.new_work, work_list[0])
         logging.info(
             f"We searched for and retrieved this same work: {WorkAPITests.new_work}"
         )
 
+    # `curl -i -X POST localhost:3000/work/demands -d '{"add_up_to": 10}'`
+    def test_submit_work_demand(self):
+        # given
+        url = "http://localhost:3000/work/demands"
+        # when
+        res = requests.post(url, json={"add_up_to": 10}, headers=AUTH)
+        # then it is queued, to poll at `Location`
+        self.assertEqual(res.status_code, 202)
+        tracking_id = json.loads(res.text)["tracking_id"]
+        self.assertEqual(res.headers["Location"], f"/work/demands/{tracking_id}")
+        # when polling it
+        res = requests.get(f"http://localhost:3000{res.headers['Location']}", headers=AUTH)
+        # then it is still queued (202) or already consumed (200)
+        self.assertIn(res.status_code, (200, 202))
+        logging.info(f"We submitted the work demand {tracking_id}: {res.text}")
+
+    # `curl -i -X GET localhost:3000/work/demands/demand-unknown`
+    def test_unknown_work_demand(self):
+        # given
+        url = "http://localhost:3000/work/demands/demand-unknown"
+        # when
+        res = requests.get(url, headers=AUTH)
+        # then a tracking id never issued is not found
+        self.assertEqual(res.status_code, 404)
+
+    # `curl -i -X GET localhost:3000/work/search`
+    def test_unauthorized(self):
+        # given
+        url = "http://localhost:3000/work/search"
+        # when
+        res = requests.get(url)
+        # then
+        self.assertEqual(res.status_code, 401)
+        self.assertEqual(res.headers["WWW-Authenticate"], "Bearer")
+        self.assertEqual(json.loads(res.text)["http_code"], 401)
+
+    # `curl -i -X DELETE 'localhost:3000/work/1000?hard=true'`
+    def test_forbidden(self):
+        # given
+        url = "http://localhost:3000/work/1?hard=true"
+        # when
+        res = requests.delete(url, headers=AUTH)
+        # then only an admin key can hard delete
+        self.assertEqual(res.status_code, 403)
+        self.assertEqual(json.loads(res.text)["http_code"], 403)
+
 
 if __name__ == "__main__":
     unittest.main()
@@ -1,8 +1,10 @@
 use structopt::StructOpt;
 
+use pp_lib::auth::{self, Scope};
 use pp_lib::factory;
-use pp_lib::model::{Error, Work};
-use pp_lib::service;
+use pp_lib::query::{WorkCursor, WorkPage, WorkQuery};
+use pp_lib::service::store::WorkStore;
+use pp_lib::Error;
 
 /// CLI wrapper to interact with the remote Work API via HTTP
 #[derive(StructOpt, Debug)]
@@ -13,16 +15,83 @@ struct ApiArgs {
     /// The string to search for the Work to retrieve
     #[structopt(required = false, long = "work-code", default_value = "")]
     work_code: String,
-    /// Decide either for HTTP call or PgSQL call
+    /// Decide either for HTTP call or storage call (PgSQL, `PP_STORE=memory` is refused)
     #[structopt(required = false, long = "call-type", default_value = "http")]
     call_type: String,
+    #[structopt(subcommand)]
+    command: Option<Command>,
 }
 
-const CALL_TYPE: &'static [&'static str] = &["http", "db"];
+#[derive(StructOpt, Debug)]
+enum Command {
+    /// Manage the API keys of the HTTP API, straight in the work store
+    Key(KeyCommand),
+}
+
+#[derive(StructOpt, Debug)]
+enum KeyCommand {
+    /// Mint a new key, its secret is only shown once
+    Mint {
+        /// Who or what the key is for
+        #[structopt(long = "name")]
+        name: String,
+        /// Comma separated scopes: work:read, work:write, admin
+        #[structopt(long = "scopes", default_value = "work:read")]
+        scopes: String,
+    },
+    /// Revoke a key, it is refused from now on
+    Revoke {
+        #[structopt(long = "id")]
+        id: i32,
+    },
+    /// List the keys, the revoked ones too
+    List,
+}
+
+fn run_key_command(command: KeyCommand) -> Result<String, Error> {
+    let mut store = factory::durable_work_store()?;
+    match command {
+        KeyCommand::Mint { name, scopes } => {
+            let scopes = Scope::parse_list(scopes.as_str())?;
+            let (key, secret) = auth::mint_key(store.as_mut(), name.as_str(), &scopes)?;
+            Ok(format!(
+                "Minted the API key {} (id {}), keep its secret, it is not stored:\n{}",
+                key.name, key.id, secret
+            ))
+        }
+        KeyCommand::Revoke { id } => store
+            .revoke_api_key(id)
+            .map(|_| format!("Revoked the API key with id {}", id)),
+        KeyCommand::List => store.list_api_keys().map(|keys| {
+            keys.iter()
+                .map(|key| serde_json::to_string(key).unwrap())
+                .collect::<Vec<String>>()
+                .join("\n")
+        }),
+    }
+}
+
+// the `Authorization` header value, when `PP_API_KEY` is set
+fn bearer() -> Option<String> {
+    std::env::var("PP_API_KEY")
+        .ok()
+        .map(|key| format!("Bearer {}", key))
+}
+
+// a GET request to the API, with the API key if any
+fn api_get(url: &str) -> ureq::Request {
+    let req = ureq::get(url);
+    match bearer() {
+        Some(bearer) => req.set("Authorization", bearer.as_str()),
+        None => req,
+    }
+}
+
+const CALL_TYPE: &[&str] = &["http", "db"];
 
 // trait to perform the call
 pub trait PpReq {
-    fn call(&self) -> String;
+    fn call(&mut self) -> String;
 }
 
 struct HttpCall {
@@ -30,53 +99,93 @@ struct HttpCall {
     work_code: String,
 }
 
+impl HttpCall {
+    // one page of the search, `cursor` is the `next_cursor` of the previous one
+    fn search_page(&self, cursor: Option<&str>) -> Result<WorkPage, String> {
+        let url = "http://localhost:3000/work/search";
+        let mut req = api_get(url).query("work_code", self.work_code.as_str());
+        if let Some(cursor) = cursor {
+            req = req.query("cursor", cursor);
+        }
+        let res: ureq::Response = req.call().map_err(|err| err.to_string())?;
+        println!(
+            "For URL {} response status: {:?}",
+            res.get_url(),
+            res.status()
+        );
+        let body = res.into_string().map_err(|err| err.to_string())?;
+        serde_json::from_str(body.as_str()).map_err(|err| err.to_string())
+    }
+}
+
 impl PpReq for HttpCall {
-    fn call(&self) -> String {
-        let url: String;
-        match self.id > 0 {
-            true => url = format!("http://localhost:3000/work/{}", self.id),
-            false => {
-                url = format!(
-                    "http://localhost:3000/work/search?work_code={}",
-                    self.work_code
-                )
+    fn call(&mut self) -> String {
+        if self.id > 0 {
+            let url = format!("http://localhost:3000/work/{}", self.id);
+            let res: ureq::Response = api_get(url.as_str()).call().unwrap();
+            println!("For URL {} response status: {:?}", url, res.status());
+            return res.into_string().unwrap();
+        }
+
+        // follow the pages until the last one, printing each as it arrives
+        let mut found: usize = 0;
+        let mut cursor: Option<String> = None;
+        loop {
+            match self.search_page(cursor.as_deref()) {
+                Ok(page) => {
+                    println!("{}", serde_json::to_string(&page.items).unwrap());
+                    found += page.items.len();
+                    cursor = page.next_cursor;
+                }
+                Err(err) => return format!("Could not search for this work, error: {}", err),
             }
-        };
-        let res: ureq::Response = ureq::get(url.as_str()).call().unwrap();
-        println!("For URL {} response status: {:?}", url, res.status());
-        res.into_string().unwrap()
+            if cursor.is_none() {
+                return format!("Found {} works", found);
+            }
+        }
     }
 }
 
-struct PgSqlCall {
+struct StoreCall {
     id: i32,
     work_code: String,
+    store: Box<dyn WorkStore + Send>,
 }
 
-impl PpReq for PgSqlCall {
-    fn call(&self) -> String {
-        // TODO make this injectable
-        let mut db = factory::db_client();
-        let res: Result<Vec<Work>, Error>;
-        match self.id > 0 {
-            true => {
-                let work_res = service::db::retrieve_work(&mut db, self.id);
-                let mut works: Vec<Work> = Vec::new();
-                works.push(work_res.unwrap());
-                res = Ok(works);
+impl StoreCall {
+    // follow the pages until the last one, printing each as it arrives, how many works were found
+    fn search_all(&mut self) -> Result<usize, Error> {
+        let mut query = WorkQuery::new().work_code_prefix(self.work_code.as_str());
+        let mut found: usize = 0;
+        loop {
+            let page = self.store.search_work_page(&query)?;
+            println!("Retrieved via the work store: {:?}", page.items);
+            found += page.items.len();
+            match page.next_cursor {
+                Some(cursor) => query = query.after(WorkCursor::decode(cursor.as_str())?),
+                None => return Ok(found),
             }
-            false => res = service::db::search_work(&mut db, self.work_code.as_str()),
+        }
+    }
+}
+
+impl PpReq for StoreCall {
+    fn call(&mut self) -> String {
+        let res: Result<String, Error> = match self.id > 0 {
+            // TODO serde "to JSON"
+            true => self
+                .store
+                .retrieve_work(self.id)
+                .map(|work| format!("Retrieved via the work store: {:?}", work)),
+            false => self
+                .search_all()
+                .map(|found| format!("Found {} works", found)),
         };
 
         match res {
-            Ok(work) => {
-                // TODO serde "to JSON"
-                return format!("Retrieved via PgSQL work: {:?}", work);
-            }
-            Err(err) => {
-                return format!("Could not find this work, error: {:?}", err);
-            }
-        };
+            Ok(output) => output,
+            Err(err) => format!("Could not find this work, error: {}", err),
+        }
     }
 }
 
@@ -89,9 +198,13 @@ fn returns_req(args: ApiArgs) -> Box<dyn PpReq> {
         };
         return Box::new(http_call);
     } else if args.call_type == "db" {
-        let db_call = PgSqlCall {
+        let db_call = StoreCall {
             id: args.id,
             work_code: args.work_code,
+            store: factory::durable_work_store().unwrap_or_else(|err| {
+                println!("Could not open the work store: {}", err);
+                std::process::exit(-1);
+            }),
         };
         return Box::new(db_call);
     }
@@ -101,22 +214,32 @@ fn returns_req(args: ApiArgs) -> Box<dyn PpReq> {
         id: args.id,
         work_code: args.work_code,
     };
-    return Box::new(http_call);
+    Box::new(http_call)
 }
 
 fn main() {
     // validation
     let args = ApiArgs::from_args();
     println!("Validating args: {:?}", args);
+    if let Some(Command::Key(command)) = args.command {
+        match run_key_command(command) {
+            Ok(output) => println!("DONE! {}", output),
+            Err(err) => {
+                println!("Could not manage the API keys, error: {}", err);
+                std::process::exit(-1);
+            }
+        }
+        return;
+    }
     if args.id <= 0 && args.id != -1 {
         println!("This ID is not valid: {}", args.id);
         std::process::exit(-1);
     }
-    if args.work_code == "" && args.id <= 0 {
+    if args.work_code.is_empty() && args.id <= 0 {
         println!("both `id` and `work_code` are not set!");
         std::process::exit(-1);
     }
-    if args.id > 0 && args.work_code.len() > 0 {
+    if args.id > 0 && !args.work_code.is_empty() {
         println!("both `id` and `work_code` are valid, we cannot use both, choose one!");
         std::process::exit(-1);
     }
@@ -125,7 +248,7 @@ fn main() {
         std::process::exit(-1);
     }
 
-    let req = returns_req(args);
+    let mut req = returns_req(args);
     let result = req.call();
     println!("DONE! {}", result);
 }
@@ -1,41 +1,321 @@
+use std::env;
 use std::io::Cursor;
+use std::panic::{self, AssertUnwindSafe};
+use std::process;
+use std::time::{Duration, Instant};
 
-use log;
-use postgres::Client;
+use chrono::Utc;
+use lazy_static::lazy_static;
 use serde_json::json;
-use tiny_http::{Method, Request, Response, StatusCode};
+use tiny_http::{Header, Method, Request, Response, StatusCode};
 
+use pp_lib::auth::{self, Scope};
+use pp_lib::metrics;
+use pp_lib::service::pool::StorePool;
+use pp_lib::service::store::WorkStore;
+use pp_lib::Error;
+
+use super::error::{body_response, error_response, internal_error_response, ErrorBody};
 use super::handler;
+use super::health;
+use super::limit::{self, RateLimiter};
+use super::openapi;
+use super::router::{Params, Routed, Router};
+
+lazy_static! {
+    // `PP_API_AUTH=false` lets every request in, only meant for local runs
+    pub static ref AUTH_ENABLED: bool = env::var("PP_API_AUTH").as_deref() != Ok("false");
+    // how many works a client can create per day (UTC), `0` for no quota,
+    // initialized when the server starts (see `main`) so a bad value stops it
+    pub static ref DAILY_WORK_QUOTA: u32 = daily_work_quota().unwrap_or_else(|err| {
+        log::error!("Not able to start: {}", err);
+        process::exit(1);
+    });
+}
 
-pub fn serve_routes(req: &mut Request, db: &mut Client) -> Response<Cursor<Vec<u8>>> {
+const DEFAULT_DAILY_WORK_QUOTA: u32 = 1000;
+
+// `PP_API_DAILY_WORK_QUOTA`, `DEFAULT_DAILY_WORK_QUOTA` when missing
+fn daily_work_quota() -> Result<u32, Error> {
+    match env::var("PP_API_DAILY_WORK_QUOTA") {
+        Ok(quota) => quota.trim().parse().map_err(|_| {
+            Error::Config(format!(
+                "Invalid PP_API_DAILY_WORK_QUOTA '{}', expected a number of works",
+                quota
+            ))
+        }),
+        Err(_) => Ok(DEFAULT_DAILY_WORK_QUOTA),
+    }
+}
+
+pub fn serve_routes(
+    req: &mut Request,
+    pool: &StorePool,
+    limiter: &RateLimiter,
+) -> Response<Cursor<Vec<u8>>> {
     log::info!(
         "New HTTP request. Method: {:?}, URL: {:?}, Headers: {:?}",
         req.method(),
         req.url(),
-        req.headers()
+        redacted_headers(req)
     );
 
-    let res: Response<Cursor<Vec<u8>>>;
+    let started = Instant::now();
+    let method = req.method().to_string();
+    // a bug hit by one request fails that request only, the worker goes on with the next one
+    let routed = panic::catch_unwind(AssertUnwindSafe(|| route(req, pool, limiter)));
+    let (route, res) = match routed {
+        Ok(routed) => routed,
+        Err(_) => {
+            log::error!("Panicked while serving {} {}", method, req.url());
+            ("panicked", internal_error_response())
+        }
+    };
+    metrics::observe_http(method.as_str(), route, res.status_code().0, started);
+    res
+}
 
-    let req_method: &Method = req.method();
-    let req_path: &str = req.url();
+// what serves a route
+pub enum Endpoint {
+    // answers even when no store is available
+    Public(fn(&StorePool) -> Response<Cursor<Vec<u8>>>),
+    Work(WorkEndpoint),
+}
 
-    // match on HTTP method + HTTP paths/params
-    if req_method == &Method::Post && handler::CREATE_WORK.is_match(req_path) {
-        // curl -i -X POST localhost:3000/work
-        res = handler::create_work(req, db)
-    } else if req_method == &Method::Get && handler::RETRIEVE_WORK.is_match(req_path) {
-        // curl -i -X GET localhost:3000/work/1000
-        res = handler::retrieve_work(req, db)
-    } else if req_method == &Method::Get && handler::SEARCH_WORK.is_match(req_path) {
+type Handler = fn(&mut Request, &Params, &mut dyn WorkStore) -> Response<Cursor<Vec<u8>>>;
+
+// a work route, it needs an API key with the scope
+pub struct WorkEndpoint {
+    scope: Scope,
+    handler: Handler,
+    // whether the works it creates count against the daily quota
+    quota: bool,
+    // `?<flag>=true` needs this scope instead
+    escalation: Option<(&'static str, Scope)>,
+}
+
+impl WorkEndpoint {
+    fn new(scope: Scope, handler: Handler) -> WorkEndpoint {
+        WorkEndpoint {
+            scope,
+            handler,
+            quota: false,
+            escalation: None,
+        }
+    }
+
+    fn with_quota(self) -> WorkEndpoint {
+        WorkEndpoint {
+            quota: true,
+            ..self
+        }
+    }
+
+    fn escalated(self, flag: &'static str, scope: Scope) -> WorkEndpoint {
+        WorkEndpoint {
+            escalation: Some((flag, scope)),
+            ..self
+        }
+    }
+
+    // the scope needed by a request, a bad flag is left to the handler
+    fn scope(&self, params: &Params) -> Scope {
+        match self.escalation {
+            Some((flag, scope)) if params.flag(flag).unwrap_or(false) => scope,
+            _ => self.scope,
+        }
+    }
+}
+
+lazy_static! {
+    // built when the server starts, see `main`
+    pub static ref ROUTER: Router<Endpoint> = Router::new()
+        // curl -i -X GET localhost:3000/healthz
+        .route(Method::Get, "/healthz", "healthz", Endpoint::Public(|_| health::healthz()))
+        // curl -i -X GET localhost:3000/readyz
+        .route(Method::Get, "/readyz", "readyz", Endpoint::Public(health::readyz))
+        // curl -i -X GET localhost:3000/metrics
+        .route(Method::Get, "/metrics", "metrics", Endpoint::Public(|_| metrics::response()))
+        // curl -i -X GET localhost:3000/openapi.json
+        .route(Method::Get, "/openapi.json", "openapi", Endpoint::Public(|_| openapi::response()))
+        // curl -i -X POST localhost:3000/work -d '{"add_up_to": 10, "metadata": {"owner": "me"}}'
+        // curl -i -X POST 'localhost:3000/work?random=true'
+        .route(Method::Post, "/work", "create_work", Endpoint::Work(WorkEndpoint::new(Scope::WorkWrite, handler::create_work).with_quota()))
+        // curl -i -X POST localhost:3000/work/demands -d '{"add_up_to": 10}'
+        .route(Method::Post, "/work/demands", "create_demand", Endpoint::Work(WorkEndpoint::new(Scope::WorkWrite, handler::create_demand).with_quota()))
+        // curl -i -X GET localhost:3000/work/demands/demand-bjq8euwsEA
+        .route(Method::Get, "/work/demands/{tracking_id}", "retrieve_demand", Endpoint::Work(WorkEndpoint::new(Scope::WorkRead, handler::retrieve_demand)))
         // curl -i -X GET localhost:3000/work/search?work_code=foo
         // curl -i -X GET localhost:3000/work/search?work_code=i97zMnpYNm
-        res = handler::search_work(req, db)
-    } else {
-        // default handler like `(_, _)` when `match (&req_method, req_path)`
-        res = Response::from_string(json!({"content": "route not found"}).to_string())
-            .with_status_code(StatusCode(404));
+        // curl -i -X GET 'localhost:3000/work/search?work_code=consumer&status=succeeded'
+        // curl -i -X GET 'localhost:3000/work/search?done=false&min_add_up_to=10&sort=-created_on&limit=5'
+        .route(Method::Get, "/work/search", "search_work", Endpoint::Work(WorkEndpoint::new(Scope::WorkRead, handler::search_work)))
+        // curl -i -X GET localhost:3000/work/1000
+        .route(Method::Get, "/work/{id:int}", "retrieve_work", Endpoint::Work(WorkEndpoint::new(Scope::WorkRead, handler::retrieve_work)))
+        // curl -i -X PUT localhost:3000/work/1000 -d '{"add_up_to": 10, "metadata": {"owner": "me"}}'
+        .route(Method::Put, "/work/{id:int}", "replace_work", Endpoint::Work(WorkEndpoint::new(Scope::WorkWrite, handler::replace_work)))
+        // curl -i -X PATCH localhost:3000/work/1000 -d '{"status": "cancelled"}'
+        .route(Method::Patch, "/work/{id:int}", "patch_work", Endpoint::Work(WorkEndpoint::new(Scope::WorkWrite, handler::patch_work)))
+        // curl -i -X DELETE localhost:3000/work/1000
+        // curl -i -X DELETE 'localhost:3000/work/1000?hard=true' (the row and its history are gone for good)
+        .route(Method::Delete, "/work/{id:int}", "delete_work", Endpoint::Work(WorkEndpoint::new(Scope::WorkWrite, handler::delete_work).escalated("hard", Scope::Admin)))
+        // curl -i -X GET localhost:3000/work/1000/events
+        .route(Method::Get, "/work/{id:int}/events", "retrieve_timeline", Endpoint::Work(WorkEndpoint::new(Scope::WorkRead, handler::retrieve_timeline)));
+}
+
+// the response along with the name of the route, the label of its metrics
+fn route(
+    req: &mut Request,
+    pool: &StorePool,
+    limiter: &RateLimiter,
+) -> (&'static str, Response<Cursor<Vec<u8>>>) {
+    match ROUTER.find(req.method(), req.url()) {
+        Routed::Found {
+            name,
+            endpoint: Endpoint::Public(serve),
+            ..
+        } => (name, serve(pool)),
+        Routed::Found {
+            name,
+            endpoint: Endpoint::Work(endpoint),
+            params,
+        } => {
+            // a client without a valid API key is limited too, before it holds a DB connection
+            let addr = format!("addr:{}", req.remote_addr().ip());
+            if let Some(rate) = limiter.check(limit::ADDR_LIMIT, addr.as_str()) {
+                if !rate.allowed {
+                    let err = Error::TooManyRequests(String::from("Too many requests, slow down"));
+                    return (name, rate.apply(error_response(&err)));
+                }
+            }
+            let served = pool
+                .with_store(|store| serve_work_route(req, store, limiter, name, endpoint, &params));
+            match served {
+                Ok(res) => (name, res),
+                Err(err) => ("unavailable", error_response(&err)),
+            }
+        }
+        // e.g. a CORS preflight, no API key needed
+        Routed::Options { allow } => (
+            "options",
+            Response::from_string("")
+                .with_header(allow_header(allow))
+                .with_status_code(StatusCode(204)),
+        ),
+        Routed::MethodNotAllowed { allow } => {
+            let message = format!("{} is not allowed here, only {}", req.method(), allow);
+            let res = body_response(&ErrorBody::new(405, message.as_str()));
+            ("method_not_allowed", res.with_header(allow_header(allow)))
+        }
+        Routed::NotFound => (
+            "not_found",
+            Response::from_string(json!({"content": "route not found"}).to_string())
+                .with_status_code(StatusCode(404)),
+        ),
+    }
+}
+
+fn allow_header(allow: String) -> Header {
+    Header::from_bytes("Allow", allow).unwrap()
+}
+
+fn serve_work_route(
+    req: &mut Request,
+    store: &mut dyn WorkStore,
+    limiter: &RateLimiter,
+    name: &'static str,
+    endpoint: &WorkEndpoint,
+    params: &Params,
+) -> Response<Cursor<Vec<u8>>> {
+    let client = match authorize(req, store, endpoint.scope(params)) {
+        Ok(client) => client,
+        Err(err) => return error_response(&err),
+    };
+
+    let rate = limiter.check(name, client.as_str());
+    let res = match rate {
+        Some(rate) if !rate.allowed => error_response(&Error::TooManyRequests(format!(
+            "Too many requests on {}, slow down",
+            name
+        ))),
+        _ if endpoint.quota => {
+            create_within_quota(req, params, store, client.as_str(), endpoint.handler)
+        }
+        _ => (endpoint.handler)(req, params, store),
+    };
+    match rate {
+        Some(rate) => rate.apply(res),
+        None => res,
     }
+}
+
+// count the work (or the demand of one) against the daily quota of `client`,
+// given back when not created
+fn create_within_quota(
+    req: &mut Request,
+    params: &Params,
+    store: &mut dyn WorkStore,
+    client: &str,
+    handler: Handler,
+) -> Response<Cursor<Vec<u8>>> {
+    if *DAILY_WORK_QUOTA == 0 {
+        return handler(req, params, store);
+    }
+    let now = Utc::now();
+    let today = now.date_naive();
+    if let Err(err) = store.take_work_quota(client, today, *DAILY_WORK_QUOTA) {
+        let res = error_response(&err);
+        return match err {
+            // the quota starts over at midnight
+            Error::TooManyRequests(_) => {
+                let retry_after = today
+                    .succ_opt()
+                    .and_then(|tomorrow| tomorrow.and_hms_opt(0, 0, 0))
+                    .and_then(|midnight| (midnight - now.naive_utc()).to_std().ok())
+                    .unwrap_or(Duration::from_secs(0));
+                limit::with_retry_after(res, retry_after)
+            }
+            _ => res,
+        };
+    }
+
+    let res = handler(req, params, store);
+    if !(200..300).contains(&res.status_code().0) {
+        if let Err(err) = store.refund_work_quota(client, today) {
+            log::error!(
+                "Not able to give back the work quota of {}: {}",
+                client,
+                err
+            );
+        }
+    }
+    res
+}
+
+/// Who is calling, the limits and quotas are counted by client:
+/// `key:<id>` of its API key, `addr:<ip>` when the keys are not checked.
+/// `Error::Unauthorized` without a valid API key, `Error::Forbidden` when it lacks `scope`.
+fn authorize(req: &Request, store: &mut dyn WorkStore, scope: Scope) -> Result<String, Error> {
+    if !*AUTH_ENABLED {
+        return Ok(format!("addr:{}", req.remote_addr().ip()));
+    }
+    let authorization = req
+        .headers()
+        .iter()
+        .find(|h| h.field.equiv("Authorization"))
+        .map(|h| h.value.as_str());
+    let key = auth::authenticate(store, authorization)?;
+    key.require(scope)?;
+    Ok(format!("key:{}", key.id))
+}
 
-    return res;
+// the headers as logged, without the secrets
+fn redacted_headers(req: &Request) -> Vec<String> {
+    req.headers()
+        .iter()
+        .map(|h| match h.field.equiv("Authorization") {
+            true => format!("{}: <redacted>", h.field),
+            false => format!("{}: {}", h.field, h.value),
+        })
+        .collect()
 }
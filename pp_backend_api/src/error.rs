@@ -0,0 +1,89 @@
+use std::io::Cursor;
+
+use schemars::JsonSchema;
+use serde::Serialize;
+use tiny_http::{Header, Response, StatusCode};
+
+use pp_lib::error::FieldError;
+use pp_lib::Error;
+
+const INTERNAL_ERROR: &str = r#"{"http_code":500,"message":"Internal server error"}"#;
+
+/// The JSON body of every failed request, same shape the API always had
+/// (plus the rejected `fields`, if any).
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct ErrorBody {
+    pub http_code: u16,
+    pub message: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub fields: Vec<FieldError>,
+}
+
+impl ErrorBody {
+    pub fn from_error(err: &Error) -> ErrorBody {
+        match err {
+            Error::NotFound(message) => ErrorBody::new(404, message),
+            Error::Conflict(message) => ErrorBody::new(409, message),
+            Error::Validation(message) => ErrorBody::new(400, message),
+            Error::Unauthorized(message) => ErrorBody::new(401, message),
+            Error::Forbidden(message) => ErrorBody::new(403, message),
+            Error::TooManyRequests(message) => ErrorBody::new(429, message),
+            Error::InvalidFields(fields) => ErrorBody {
+                fields: fields.clone(),
+                ..ErrorBody::new(422, "Invalid fields")
+            },
+            // the demands can be sent again once the broker is back
+            Error::Broker { .. } => {
+                log::error!("Broker error: {}", err);
+                ErrorBody::new(503, "The work queue is unavailable, try again later")
+            }
+            // do not leak DB/broker details to the clients, keep them in the logs
+            other => {
+                log::error!("Internal error: {}", other);
+                ErrorBody::new(500, "Internal server error")
+            }
+        }
+    }
+
+    pub fn new(http_code: u16, message: &str) -> ErrorBody {
+        ErrorBody {
+            http_code,
+            message: String::from(message),
+            fields: Vec::new(),
+        }
+    }
+}
+
+pub fn error_response(err: &Error) -> Response<Cursor<Vec<u8>>> {
+    let res = body_response(&ErrorBody::from_error(err));
+    match res.status_code().0 {
+        // tell the client how to authenticate
+        401 => res.with_header(Header::from_bytes("WWW-Authenticate", "Bearer").unwrap()),
+        _ => res,
+    }
+}
+
+/// The response of a failed request, e.g. a `405` from the router.
+pub fn body_response(body: &ErrorBody) -> Response<Cursor<Vec<u8>>> {
+    let json = serde_json::to_string(body).unwrap_or_else(|err| {
+        log::error!("Not able to serialize the error body: {}", err);
+        String::from(INTERNAL_ERROR)
+    });
+    Response::from_string(json).with_status_code(StatusCode(body.http_code))
+}
+
+/// `value` as the JSON body, a `500` when it does not serialize.
+pub fn json_response<T: Serialize>(value: &T, http_code: u16) -> Response<Cursor<Vec<u8>>> {
+    match serde_json::to_string(value) {
+        Ok(json) => Response::from_string(json).with_status_code(StatusCode(http_code)),
+        Err(err) => error_response(&Error::serialization(
+            "Not able to serialize the response",
+            err,
+        )),
+    }
+}
+
+/// The `500` of a request which could not be served, e.g. its handler panicked.
+pub fn internal_error_response() -> Response<Cursor<Vec<u8>>> {
+    Response::from_string(INTERNAL_ERROR).with_status_code(StatusCode(500))
+}
@@ -1,114 +1,254 @@
-use std::io::Cursor;
+use std::io::{Cursor, Read};
 
-use lazy_static::lazy_static;
-use log;
-use postgres::Client;
-use regex::Regex;
-use serde_json;
-use tiny_http::{Request, Response, StatusCode};
+use serde::de::DeserializeOwned;
+use tiny_http::{Header, Request, Response, StatusCode};
 
 use pp_lib::factory;
-use pp_lib::model::Work;
-use pp_lib::service;
-
-// We want to be able to recognize URL strings and extract information out of them.
-// The regex library follows the RE2 standard (Golang regex https://github.com/google/re2).
-// We can write our regex and test their matches on: https://regex101.com/
-lazy_static! {
-    // Recognize simple URL paths:
-    // /work
-    // /work/
-    pub static ref CREATE_WORK: Regex = Regex::new("^/work/?$").unwrap();
-    // Recognize URL path parameters:
-    // RE2: ^/work/((?P<id>\d+?)/?)?$
-    // examples:
-    // /work/123/
-    // /work/123
-    // => extract id=123
-    pub static ref RETRIEVE_WORK: Regex = Regex::new("^/work/((?P<id>\\d+?)/?)?$").unwrap();
-    // Recognize URL query parameters:
-    // RE2: ^/work/search/?\?(work_code=)(?P<work_code>[a-zA-Z0-9-]+?)&?$
-    // examples:
-    // /work/search?work_code=123
-    // /work/search/?work_code=foo
-    // /work/search/?work_code=foo1bar2baz3
-    // => extract work_code=xxx
-    pub static ref SEARCH_WORK: Regex = Regex::new("^/work/search/?\\?(work_code=)(?P<work_code>[a-zA-Z0-9-]+?)&?$").unwrap();
-}
+use pp_lib::model::{
+    NewWork, WorkDemand, WorkDemandReceipt, WorkPatch, WorkReplace, WorkStatus, WorkUpdate,
+};
+use pp_lib::query::WorkQuery;
+use pp_lib::service::queue;
+use pp_lib::service::store::WorkStore;
+use pp_lib::Error;
 
-pub fn create_work(_req: &mut Request, db: &mut Client) -> Response<Cursor<Vec<u8>>> {
-    let res: Response<Cursor<Vec<u8>>>;
+use super::error::{error_response, json_response};
+use super::router::Params;
 
-    // generate some random "work context"
-    let work: Work = factory::generate_random_work("api");
+// larger bodies are refused without being read in full
+const MAX_BODY_BYTES: usize = 16 * 1024;
 
-    match service::db::create_work(db, work) {
-        Ok(work) => {
-            res = Response::from_string(serde_json::to_string(&work).unwrap())
-                .with_status_code(StatusCode(200));
-        }
-        Err(err) => {
-            res = Response::from_string(serde_json::to_string(&err).unwrap())
-                .with_status_code(StatusCode(err.http_code));
-        }
+/// Read and parse the JSON body, `hint` tells what is expected when it is missing.
+fn read_json<T: DeserializeOwned>(req: &mut Request, hint: &str) -> Result<T, Error> {
+    let mut body = String::new();
+    req.as_reader()
+        .take(MAX_BODY_BYTES as u64 + 1)
+        .read_to_string(&mut body)
+        .map_err(|err| Error::Validation(format!("Not able to read the body: {}", err)))?;
+    if body.len() > MAX_BODY_BYTES {
+        return Err(Error::Validation(format!(
+            "The body is larger than {} bytes",
+            MAX_BODY_BYTES
+        )));
     }
+    if body.trim().is_empty() {
+        return Err(Error::Validation(format!("Missing JSON body, {}", hint)));
+    }
+    serde_json::from_str(body.as_str())
+        .map_err(|err| Error::Validation(format!("Invalid JSON body: {}", err)))
+}
 
-    return res;
+pub fn create_work(
+    req: &mut Request,
+    params: &Params,
+    store: &mut dyn WorkStore,
+) -> Response<Cursor<Vec<u8>>> {
+    let res = match params.flag("random") {
+        Err(err) => Err(err),
+        // generate some random "work context"
+        Ok(true) => store.create_work_retrying(factory::generate_random_work("api")),
+        Ok(false) => read_json::<NewWork>(
+            req,
+            "e.g. {\"add_up_to\": 10}, or ask for a random work with ?random=true",
+        )
+        .and_then(|new_work| new_work.validate().map(|_| new_work))
+        .and_then(|new_work| match new_work.work_code {
+            // a `work_code` chosen by the client is never replaced, it is a conflict instead
+            Some(_) => store.create_work(factory::map_new_work(new_work, "api")),
+            None => store.create_work_retrying(factory::map_new_work(new_work, "api")),
+        }),
+    };
+
+    match res {
+        Ok(work) => json_response(&work, 200),
+        Err(err) => error_response(&err),
+    }
 }
 
-pub fn retrieve_work(req: &mut Request, db: &mut Client) -> Response<Cursor<Vec<u8>>> {
-    let res: Response<Cursor<Vec<u8>>>;
-
-    // regex on the HTTP path to find the row ID (or row random string?)
-    let req_path: &str = req.url();
-    let id: i32 = RETRIEVE_WORK
-        .captures(req_path)
-        .and_then(|id_cap| id_cap.name("id").map(|id| id.as_str()))
-        .unwrap()
-        .parse::<i32>()
-        .unwrap();
+pub fn retrieve_work(
+    _req: &mut Request,
+    params: &Params,
+    store: &mut dyn WorkStore,
+) -> Response<Cursor<Vec<u8>>> {
+    let id: i32 = match params.get("id") {
+        Ok(id) => id,
+        Err(err) => return error_response(&err),
+    };
     log::info!("The HTTP req provided for the retrieval the id: {}", id);
 
-    match service::db::retrieve_work(db, id) {
-        Ok(work) => {
-            res = Response::from_string(serde_json::to_string(&work).unwrap())
-                .with_status_code(StatusCode(200));
-        }
-        Err(err) => {
-            res = Response::from_string(serde_json::to_string(&err).unwrap())
-                .with_status_code(StatusCode(err.http_code));
-        }
+    match store.retrieve_work(id) {
+        Ok(work) => json_response(&work, 200),
+        Err(err) => error_response(&err),
     }
-    return res;
 }
 
-pub fn search_work(req: &mut Request, db: &mut Client) -> Response<Cursor<Vec<u8>>> {
-    let res: Response<Cursor<Vec<u8>>>;
-
-    // regex on the HTTP path to find the row work_code parameter
-    let req_path: &str = req.url();
-    let work_code: &str = SEARCH_WORK
-        .captures(req_path)
-        .and_then(|work_code_cap| {
-            work_code_cap
-                .name("work_code")
-                .map(|work_code| work_code.as_str())
-        })
-        .unwrap();
+pub fn retrieve_timeline(
+    _req: &mut Request,
+    params: &Params,
+    store: &mut dyn WorkStore,
+) -> Response<Cursor<Vec<u8>>> {
+    let res = params.get("id").and_then(|id| {
+        log::info!("The HTTP req provided for the timeline the id: {}", id);
+        store.retrieve_timeline(id)
+    });
+    match res {
+        Ok(timeline) => json_response(&timeline, 200),
+        Err(err) => error_response(&err),
+    }
+}
+
+pub fn search_work(
+    _req: &mut Request,
+    params: &Params,
+    store: &mut dyn WorkStore,
+) -> Response<Cursor<Vec<u8>>> {
+    let query_string: &str = params.query();
     log::info!(
-        "The HTTP req provided for the search the work_code: {}",
-        work_code
+        "The HTTP req provided for the search the query string: {}",
+        query_string
     );
 
-    match service::db::search_work(db, work_code) {
-        Ok(work) => {
-            res = Response::from_string(serde_json::to_string(&work).unwrap())
-                .with_status_code(StatusCode(200));
+    match WorkQuery::from_query_string(query_string)
+        .and_then(|query| store.search_work_page(&query))
+    {
+        Ok(page) => json_response(&page, 200),
+        Err(err) => error_response(&err),
+    }
+}
+
+fn update_work(
+    params: &Params,
+    store: &mut dyn WorkStore,
+    update: Result<WorkUpdate, Error>,
+) -> Response<Cursor<Vec<u8>>> {
+    let res = params
+        .get("id")
+        .and_then(|id| update.map(|update| (id, update)))
+        .and_then(|(id, update)| {
+            log::info!("The HTTP req provided for the update the id: {}", id);
+            store.update_work(id, &update)
+        });
+    match res {
+        Ok(work) => json_response(&work, 200),
+        Err(err) => error_response(&err),
+    }
+}
+
+pub fn replace_work(
+    req: &mut Request,
+    params: &Params,
+    store: &mut dyn WorkStore,
+) -> Response<Cursor<Vec<u8>>> {
+    let update = read_json::<WorkReplace>(req, "e.g. {\"add_up_to\": 10}")
+        .and_then(|replace| replace.validate().map(|_| WorkUpdate::from(replace)));
+    update_work(params, store, update)
+}
+
+pub fn patch_work(
+    req: &mut Request,
+    params: &Params,
+    store: &mut dyn WorkStore,
+) -> Response<Cursor<Vec<u8>>> {
+    let update = read_json::<WorkPatch>(req, "e.g. {\"status\": \"cancelled\"}")
+        .and_then(|patch| patch.validate().map(|_| WorkUpdate::from(patch)));
+    update_work(params, store, update)
+}
+
+pub fn delete_work(
+    _req: &mut Request,
+    params: &Params,
+    store: &mut dyn WorkStore,
+) -> Response<Cursor<Vec<u8>>> {
+    // `?hard=true` removes the row instead of hiding it
+    let res = params.flag("hard").and_then(|hard| {
+        let id: i32 = params.get("id")?;
+        log::info!(
+            "The HTTP req provided for the deletion (hard: {}) the id: {}",
+            hard,
+            id
+        );
+        match hard {
+            true => store.delete_work(id),
+            false => store.soft_delete_work(id),
         }
-        Err(err) => {
-            res = Response::from_string(serde_json::to_string(&err).unwrap())
-                .with_status_code(StatusCode(err.http_code));
+    });
+    match res {
+        Ok(_) => Response::from_string("").with_status_code(StatusCode(204)),
+        Err(err) => error_response(&err),
+    }
+}
+
+/// Store the `Queued` work of the demand, coded with its tracking id, then publish
+/// the demand on the AMQP queue: `task_consumer` computes the work later.
+pub fn create_demand(
+    req: &mut Request,
+    _params: &Params,
+    store: &mut dyn WorkStore,
+) -> Response<Cursor<Vec<u8>>> {
+    let (demand, _) = match read_json::<WorkDemand>(req, "e.g. {\"add_up_to\": 10}")
+        .and_then(|demand| demand.validate().map(|_| demand))
+    {
+        Ok(demand) => factory::track_work_demand(demand),
+        Err(err) => return error_response(&err),
+    };
+    // a tracking id already taken is drawn again, like the random work codes
+    let work = match store.create_work_retrying(factory::map_to_work(demand.clone(), "api")) {
+        Ok(work) => work,
+        Err(err) => return error_response(&err),
+    };
+    let tracking_id = work.work_code.clone();
+    let demand = WorkDemand {
+        tracking_id: Some(tracking_id.clone()),
+        ..demand
+    };
+    if let Err(err) = queue::publish(&demand) {
+        // nobody will ever compute it, the tracking id must not be polled forever
+        if let Err(del_err) = store.delete_work(work.id) {
+            log::error!(
+                "Failed to remove the work of an unpublished demand: {}",
+                del_err
+            );
         }
+        return error_response(&err);
+    }
+    demand_accepted(tracking_id)
+}
+
+/// The work of the demand, `202` while it is still in the queue.
+pub fn retrieve_demand(
+    _req: &mut Request,
+    params: &Params,
+    store: &mut dyn WorkStore,
+) -> Response<Cursor<Vec<u8>>> {
+    let tracking_id: String = match params.get("tracking_id") {
+        Ok(tracking_id) => tracking_id,
+        Err(err) => return error_response(&err),
+    };
+    if !factory::is_tracking_id(tracking_id.as_str()) {
+        return error_response(&Error::NotFound(format!(
+            "No work demand tracked as {}",
+            tracking_id
+        )));
+    }
+    log::info!(
+        "The HTTP req provided for the demand the tracking id: {}",
+        tracking_id
+    );
+
+    // never issued or deleted since: not found
+    match store.find_work_by_code(tracking_id.as_str()) {
+        Ok(work) if work.status == WorkStatus::Queued => demand_accepted(tracking_id),
+        Ok(work) => json_response(&work, 200),
+        Err(err) => error_response(&err),
+    }
+}
+
+// where to poll the demand
+fn demand_accepted(tracking_id: String) -> Response<Cursor<Vec<u8>>> {
+    let location = format!("/work/demands/{}", tracking_id);
+    let res = json_response(&WorkDemandReceipt { tracking_id }, 202);
+    match Header::from_bytes("Location", location) {
+        Ok(header) => res.with_header(header),
+        Err(_) => res,
     }
-    return res;
 }
@@ -0,0 +1,67 @@
+use std::collections::BTreeMap;
+use std::env;
+use std::io::Cursor;
+
+use schemars::JsonSchema;
+use serde::Serialize;
+use tiny_http::{Response, StatusCode};
+
+use pp_lib::service::pool::StorePool;
+use pp_lib::service::queue;
+use pp_lib::Error;
+
+use super::error::json_response;
+
+/// The state of one dependency in the `GET /readyz` body.
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct Check {
+    pub status: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<&'static str>,
+}
+
+impl Check {
+    // the details (hosts, users...) only go to the logs
+    fn from_result(dependency: &str, res: Result<(), Error>) -> Check {
+        match res {
+            Ok(_) => Check {
+                status: "up",
+                error: None,
+            },
+            Err(err) => {
+                log::error!("Readiness check of {} failed: {}", dependency, err);
+                Check {
+                    status: "down",
+                    error: Some("unavailable"),
+                }
+            }
+        }
+    }
+}
+
+/// The JSON body of `GET /readyz`, the checks by dependency.
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct ReadyBody {
+    pub status: &'static str,
+    pub checks: BTreeMap<&'static str, Check>,
+}
+
+pub fn healthz() -> Response<Cursor<Vec<u8>>> {
+    Response::from_string(r#"{"status":"alive"}"#).with_status_code(StatusCode(200))
+}
+
+/// Check the storage and, when `PP_API_CHECK_AMQP=true`, the AMQP broker.
+pub fn readyz(pool: &StorePool) -> Response<Cursor<Vec<u8>>> {
+    let mut checks = BTreeMap::new();
+    checks.insert(pool.kind(), Check::from_result(pool.kind(), pool.ping()));
+    if env::var("PP_API_CHECK_AMQP").as_deref() == Ok("true") {
+        checks.insert("amqp", Check::from_result("amqp", queue::ping()));
+    }
+
+    let ready = checks.values().all(|check| check.error.is_none());
+    let body = ReadyBody {
+        status: if ready { "ready" } else { "not_ready" },
+        checks,
+    };
+    json_response(&body, if ready { 200 } else { 503 })
+}
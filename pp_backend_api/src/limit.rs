@@ -0,0 +1,197 @@
+use std::collections::HashMap;
+use std::env;
+use std::io::Cursor;
+use std::str::FromStr;
+use std::sync::{Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use tiny_http::{Header, Response};
+
+use pp_lib::Error;
+
+// a burst of 60 works a minute, the reads are cheaper
+pub const DEFAULT_RATE_LIMITS: &str = "addr=1200/60,*=600/60,create_work=60/60,create_demand=60/60";
+// the limit of every work request from a remote address, checked before the API key
+pub const ADDR_LIMIT: &str = "addr";
+// the buckets refilled in full are dropped beyond that many clients
+const MAX_BUCKETS: usize = 10_000;
+
+/// `capacity` requests at once, then refilled evenly over `period`, e.g. `60/60` in the config.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RateLimit {
+    pub capacity: u32,
+    pub period: Duration,
+}
+
+impl RateLimit {
+    // tokens per second
+    fn rate(&self) -> f64 {
+        f64::from(self.capacity) / self.period.as_secs_f64()
+    }
+}
+
+impl FromStr for RateLimit {
+    type Err = Error;
+
+    fn from_str(value: &str) -> Result<RateLimit, Error> {
+        let invalid = || {
+            Error::Config(format!(
+                "Invalid rate limit '{}', expected <requests>/<seconds> e.g. 60/60",
+                value
+            ))
+        };
+        let (capacity, secs) = value.split_once('/').ok_or_else(invalid)?;
+        let capacity: u32 = capacity.trim().parse().map_err(|_| invalid())?;
+        let secs: u64 = secs.trim().parse().map_err(|_| invalid())?;
+        if capacity == 0 || secs == 0 {
+            return Err(invalid());
+        }
+        Ok(RateLimit {
+            capacity,
+            period: Duration::from_secs(secs),
+        })
+    }
+}
+
+/// Where a client stands on a route after a request.
+#[derive(Clone, Copy, Debug)]
+pub struct RateStatus {
+    pub allowed: bool,
+    pub limit: u32,
+    pub remaining: u32,
+    // until the bucket is full again
+    pub reset: Duration,
+    // until the next request is allowed
+    pub retry_after: Duration,
+}
+
+impl RateStatus {
+    /// Add the `RateLimit-*` headers (and `Retry-After` when refused) to `res`.
+    pub fn apply(&self, res: Response<Cursor<Vec<u8>>>) -> Response<Cursor<Vec<u8>>> {
+        let mut res = res
+            .with_header(header("RateLimit-Limit", self.limit.to_string()))
+            .with_header(header("RateLimit-Remaining", self.remaining.to_string()))
+            .with_header(header("RateLimit-Reset", ceil_secs(self.reset).to_string()));
+        if !self.allowed {
+            res = with_retry_after(res, self.retry_after);
+        }
+        res
+    }
+}
+
+/// Add `Retry-After` to `res`, in whole seconds.
+pub fn with_retry_after(
+    res: Response<Cursor<Vec<u8>>>,
+    retry_after: Duration,
+) -> Response<Cursor<Vec<u8>>> {
+    res.with_header(header("Retry-After", ceil_secs(retry_after).to_string()))
+}
+
+fn header(name: &str, value: String) -> Header {
+    Header::from_bytes(name, value).unwrap()
+}
+
+fn ceil_secs(duration: Duration) -> u64 {
+    duration.as_secs() + u64::from(duration.subsec_nanos() > 0)
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    refilled: Instant,
+}
+
+impl Bucket {
+    fn refill(&mut self, limit: &RateLimit, now: Instant) {
+        let elapsed = now.duration_since(self.refilled).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * limit.rate()).min(f64::from(limit.capacity));
+        self.refilled = now;
+    }
+}
+
+/// A token bucket per route and client, shared by the workers of the process.
+#[derive(Debug)]
+pub struct RateLimiter {
+    // by route name, `*` for the routes not listed
+    limits: HashMap<String, RateLimit>,
+    buckets: Mutex<HashMap<(&'static str, String), Bucket>>,
+}
+
+impl RateLimiter {
+    /// The limits of a comma separated list of `<route>=<requests>/<seconds>`,
+    /// e.g. `*=600/60,create_work=60/60`, a route without limit is never refused.
+    pub fn new(config: &str) -> Result<RateLimiter, Error> {
+        let mut limits = HashMap::new();
+        for entry in config.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+            let (route, limit) = entry.split_once('=').ok_or_else(|| {
+                Error::Config(format!(
+                    "Invalid rate limit '{}', expected <route>=<requests>/<seconds>",
+                    entry
+                ))
+            })?;
+            limits.insert(String::from(route.trim()), limit.parse()?);
+        }
+        Ok(RateLimiter {
+            limits,
+            buckets: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// The limits of `PP_API_RATE_LIMITS`, `DEFAULT_RATE_LIMITS` when missing.
+    pub fn from_env() -> Result<RateLimiter, Error> {
+        let config = env::var("PP_API_RATE_LIMITS");
+        RateLimiter::new(config.as_deref().unwrap_or(DEFAULT_RATE_LIMITS))
+    }
+
+    fn limit_of(&self, route: &str) -> Option<&RateLimit> {
+        self.limits.get(route).or_else(|| self.limits.get("*"))
+    }
+
+    /// Take a token for a request of `client` on `route`, `None` when the route has no limit.
+    pub fn check(&self, route: &'static str, client: &str) -> Option<RateStatus> {
+        let limit = *self.limit_of(route)?;
+        let now = Instant::now();
+        let mut buckets = self.lock();
+        if buckets.len() >= MAX_BUCKETS {
+            self.forget_full(&mut buckets, now);
+        }
+        let bucket = buckets
+            .entry((route, String::from(client)))
+            .or_insert(Bucket {
+                tokens: f64::from(limit.capacity),
+                refilled: now,
+            });
+        bucket.refill(&limit, now);
+        let allowed = bucket.tokens >= 1.0;
+        if allowed {
+            bucket.tokens -= 1.0;
+        }
+        let missing = |tokens: f64| Duration::from_secs_f64(tokens.max(0.0) / limit.rate());
+        Some(RateStatus {
+            allowed,
+            limit: limit.capacity,
+            remaining: bucket.tokens.floor() as u32,
+            reset: missing(f64::from(limit.capacity) - bucket.tokens),
+            retry_after: missing(1.0 - bucket.tokens),
+        })
+    }
+
+    // a full bucket is the same as no bucket
+    fn forget_full(&self, buckets: &mut HashMap<(&'static str, String), Bucket>, now: Instant) {
+        buckets.retain(|(route, _), bucket| match self.limit_of(route) {
+            Some(limit) => {
+                bucket.refill(limit, now);
+                bucket.tokens < f64::from(limit.capacity)
+            }
+            None => false,
+        });
+    }
+
+    fn lock(&self) -> MutexGuard<'_, HashMap<(&'static str, String), Bucket>> {
+        // the buckets stay usable even if a worker panicked while holding the lock
+        match self.buckets.lock() {
+            Ok(guard) => guard,
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
+}
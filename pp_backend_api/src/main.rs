@@ -1,16 +1,68 @@
-use std::sync::Arc;
+use std::env;
+use std::process;
+use std::sync::mpsc::{self, Receiver};
+use std::sync::{Arc, Mutex, PoisonError};
 use std::thread::spawn;
+use std::time::{Duration, Instant};
 
 use env_logger::Env;
-use log;
-use tiny_http::Server;
+use tiny_http::{Request, Server};
 
 use pp_lib::factory;
+use pp_lib::service::pool::StorePool;
+
+use limit::RateLimiter;
 
 mod api;
+mod error;
 mod handler;
+mod health;
+mod limit;
+mod openapi;
+mod router;
+
+const HTTP_PORT: &str = "3000";
+// how many requests are served at the same time (and how many DB connections are pooled by default)
+const DEFAULT_WORKERS: u32 = 4;
+// how long the requests in flight have to complete after SIGTERM/SIGINT
+const DEFAULT_SHUTDOWN_SECS: u32 = 30;
+// how often the listener checks whether the server is shutting down
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+// a positive number from the environment variable `name`, `default` when missing
+fn env_number(name: &str, default: u32) -> u32 {
+    match env::var(name) {
+        Ok(value) => match value.parse::<u32>() {
+            Ok(number) if number > 0 => number,
+            _ => {
+                log::error!("Not able to start: {} must be a positive number", name);
+                process::exit(1);
+            }
+        },
+        Err(_) => default,
+    }
+}
+
+/// Serve the requests handed over until the listener is closed and none is left.
+fn serve(requests: &Mutex<Receiver<Request>>, pool: &StorePool, limiter: &RateLimiter) {
+    loop {
+        let received = requests
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .recv();
+        let mut rq = match received {
+            Ok(rq) => rq,
+            Err(_) => return,
+        };
 
-const HTTP_PORT: &'static str = "3000";
+        let res = api::serve_routes(&mut rq, pool, limiter);
+        let result = rq.respond(res);
+        match result {
+            Ok(result) => log::info!("Done with result: {:?}", result),
+            Err(err) => log::error!("Failed to respond to request: {}", err),
+        }
+    }
+}
 
 fn main() {
     // https://github.com/env-logger-rs/env_logger/blob/main/examples/default.rs
@@ -23,28 +75,114 @@ fn main() {
         .write_style_or("MY_LOG_STYLE", "always");
     env_logger::init_from_env(env);
 
+    let http_port = env::var("PP_API_PORT").unwrap_or_else(|_| String::from(HTTP_PORT));
+    let workers = env_number("PP_API_WORKERS", DEFAULT_WORKERS);
+    let connections = env_number("PP_API_DB_CONNECTIONS", workers);
+    let shutdown_deadline = Duration::from_secs(u64::from(env_number(
+        "PP_API_SHUTDOWN_SECS",
+        DEFAULT_SHUTDOWN_SECS,
+    )));
+
+    // the buckets are shared by the workers, a client is limited whichever serves it
+    let limiter = match RateLimiter::from_env() {
+        Ok(limiter) => Arc::new(limiter),
+        Err(err) => {
+            log::error!("Not able to start: {}", err);
+            process::exit(1);
+        }
+    };
+
+    let (signal_tx, signal_rx) = mpsc::channel();
+    if let Err(err) = ctrlc::set_handler(move || {
+        let _ = signal_tx.send(());
+    }) {
+        log::error!("Not able to start: {}", err);
+        process::exit(1);
+    }
+
+    let pool = match factory::work_store_pool(connections) {
+        Ok(pool) => pool,
+        Err(err) => {
+            log::error!("Not able to start: {}", err);
+            process::exit(1);
+        }
+    };
+    if let Err(err) = pool
+        .with_store(|store| store.check_schema())
+        .and_then(|res| res)
+    {
+        log::error!("Not able to start: {}", err);
+        process::exit(1);
+    }
+
+    // a bad route pattern (or quota) stops the start instead of failing every request
+    lazy_static::initialize(&api::ROUTER);
+    lazy_static::initialize(&api::DAILY_WORK_QUOTA);
+    for (method, pattern) in api::ROUTER.routes() {
+        log::info!("Serving {} {}", method, pattern);
+    }
+
     // sudo netstat -tunlp | grep '3000.*LISTEN'
-    let server = Arc::new(Server::http(format!("0.0.0.0:{}", &HTTP_PORT)).unwrap());
-    let msg = String::from(format!("Now listening on port {}", &HTTP_PORT));
-    log::info!("{}", msg);
-
-    let mut db = factory::db_client();
-
-    let mut handles = Vec::new();
-    handles.push(spawn(move || {
-        for mut rq in server.incoming_requests() {
-            // return the variable ownership after borrowing...
-            let res = api::serve_routes(&mut rq, &mut db);
-
-            let result = rq.respond(res);
-            match result {
-                Ok(result) => log::info!("Done with result: {:?}", result),
-                Err(err) => log::error!("Failed to respond to request: {}", err),
+    let server = Server::http(format!("0.0.0.0:{}", &http_port)).unwrap();
+    log::info!(
+        "Now listening on port {} with {} workers",
+        &http_port,
+        workers
+    );
+    if !*api::AUTH_ENABLED {
+        log::warn!("PP_API_AUTH=false, the work API is open to anyone");
+    }
+
+    // every worker pulls the next request received by the listener
+    let (request_tx, request_rx) = mpsc::channel();
+    let request_rx = Arc::new(Mutex::new(request_rx));
+    let (done_tx, done_rx) = mpsc::channel();
+    for _ in 0..workers {
+        let request_rx = Arc::clone(&request_rx);
+        let pool = pool.clone();
+        let limiter = Arc::clone(&limiter);
+        let done_tx = done_tx.clone();
+        spawn(move || {
+            serve(&request_rx, &pool, &limiter);
+            let _ = done_tx.send(());
+        });
+    }
+
+    // until SIGTERM/SIGINT
+    while signal_rx.try_recv().is_err() {
+        match server.recv_timeout(POLL_INTERVAL) {
+            Ok(Some(rq)) => {
+                let _ = request_tx.send(rq);
             }
+            Ok(None) => {}
+            Err(err) => log::error!("Failed to receive a request: {}", err),
         }
-    }));
+    }
 
-    for h in handles {
-        h.join().unwrap();
+    // stop listening first, then let the workers complete what they have received
+    drop(server);
+    drop(request_tx);
+    log::info!(
+        "Closed the listener, draining the requests received for at most {:?}",
+        shutdown_deadline
+    );
+    let deadline = Instant::now() + shutdown_deadline;
+    let mut busy = workers;
+    while busy > 0 {
+        match done_rx.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+            Ok(()) => busy -= 1,
+            Err(_) => {
+                log::error!(
+                    "{} workers still busy after {:?}, exiting anyway",
+                    busy,
+                    shutdown_deadline
+                );
+                process::exit(1);
+            }
+        }
     }
+
+    // the workers are gone, so are their handles on the pool
+    drop(pool);
+    log::info!("Closed the DB connections, bye");
 }
@@ -0,0 +1,416 @@
+use std::io::Cursor;
+
+use lazy_static::lazy_static;
+use schemars::gen::{SchemaGenerator, SchemaSettings};
+use schemars::schema::Schema;
+use schemars::JsonSchema;
+use serde_json::{json, Map, Value};
+use tiny_http::{Header, Response, StatusCode};
+
+use pp_lib::auth::Scope;
+use pp_lib::model::{
+    Event, NewWork, Work, WorkDemand, WorkDemandReceipt, WorkPatch, WorkReplace, WorkStatus,
+    WorkTimeline,
+};
+use pp_lib::query::{WorkPage, WorkSortField, DEFAULT_PAGE_SIZE, MAX_PAGE_SIZE, WORK_QUERY_PARAMS};
+
+use super::error::ErrorBody;
+use super::health::ReadyBody;
+
+lazy_static! {
+    // the document never changes while the server runs
+    static ref DOCUMENT: String = document().to_string();
+}
+
+/// The `GET /openapi.json` response.
+pub fn response() -> Response<Cursor<Vec<u8>>> {
+    let content_type = Header::from_bytes("Content-Type", "application/json").unwrap();
+    Response::from_string(DOCUMENT.as_str())
+        .with_header(content_type)
+        .with_status_code(StatusCode(200))
+}
+
+/// The OpenAPI 3.0 document of the API, its schemas are derived from the Rust types
+/// (`pp_lib::model`, `pp_lib::query`, `ErrorBody`) so that they follow the code.
+pub fn document() -> Value {
+    let mut gen = SchemaSettings::openapi3().into_generator();
+    let any_object = json!({"type": "object"});
+    let (read, write) = (Scope::WorkRead.as_str(), Scope::WorkWrite.as_str());
+
+    let healthz = operation(
+        "The process is alive",
+        vec![],
+        None,
+        vec![response_of(200, "Alive", Some(any_object.clone()))],
+    );
+    let readyz = operation(
+        "The storage (and the AMQP broker, when `PP_API_CHECK_AMQP=true`) can be reached",
+        vec![],
+        None,
+        vec![
+            response_of(200, "Ready", Some(schema_for::<ReadyBody>(&mut gen))),
+            response_of(503, "Not ready", Some(schema_for::<ReadyBody>(&mut gen))),
+        ],
+    );
+    let metrics = json!({
+        "summary": "The metrics in the Prometheus text format",
+        "responses": {
+            "200": {
+                "description": "The metrics",
+                "content": {"text/plain": {"schema": {"type": "string"}}},
+            },
+        },
+    });
+    let openapi = operation(
+        "This document",
+        vec![],
+        None,
+        vec![response_of(200, "The OpenAPI document", Some(any_object))],
+    );
+
+    let create_work = operation(
+        "Create a work, a random one with `?random=true` (no body then)",
+        vec![query_param(
+            "random",
+            "Ignore the body and draw a random work",
+            json!({"type": "boolean", "default": false}),
+        )],
+        Some(schema_for::<NewWork>(&mut gen)),
+        vec![
+            response_of(200, "The work created", Some(schema_for::<Work>(&mut gen))),
+            error_of(400, "Missing or malformed body", &mut gen),
+            error_of(409, "The `work_code` is taken", &mut gen),
+            error_of(422, "Some fields are out of bounds", &mut gen),
+        ],
+    );
+    let create_demand = operation(
+        "Queue a work demand, stored as a `queued` work `task_consumer` computes later",
+        vec![],
+        Some(schema_for::<WorkDemand>(&mut gen)),
+        vec![
+            demand_accepted(&mut gen),
+            error_of(400, "Missing or malformed body", &mut gen),
+            error_of(422, "Some fields are out of bounds", &mut gen),
+            error_of(503, "The work queue is unavailable", &mut gen),
+        ],
+    );
+    let retrieve_demand = operation(
+        "Poll a work demand: its work once taken over, `202` while still queued",
+        vec![json!({
+            "name": "tracking_id",
+            "in": "path",
+            "required": true,
+            "schema": {"type": "string"},
+        })],
+        None,
+        vec![
+            response_of(200, "The work", Some(schema_for::<Work>(&mut gen))),
+            demand_accepted(&mut gen),
+            error_of(
+                404,
+                "No such tracking id, or its work was deleted",
+                &mut gen,
+            ),
+        ],
+    );
+    let retrieve_work = operation(
+        "Retrieve a work",
+        vec![id_param()],
+        None,
+        vec![
+            response_of(200, "The work", Some(schema_for::<Work>(&mut gen))),
+            error_of(404, "No such work", &mut gen),
+        ],
+    );
+    let replace_work = operation(
+        "Replace every field a client can change",
+        vec![id_param()],
+        Some(schema_for::<WorkReplace>(&mut gen)),
+        update_responses(&mut gen),
+    );
+    let patch_work = operation(
+        "Change only the fields present, e.g. cancel the work",
+        vec![id_param()],
+        Some(schema_for::<WorkPatch>(&mut gen)),
+        update_responses(&mut gen),
+    );
+    let delete_work = operation(
+        "Delete a work, softly unless `?hard=true`",
+        vec![
+            id_param(),
+            query_param(
+                "hard",
+                "Remove the row of the work instead of hiding it, only once it has no events",
+                json!({"type": "boolean", "default": false}),
+            ),
+        ],
+        None,
+        vec![
+            response_of(204, "Deleted", None),
+            error_of(
+                403,
+                "The API key lacks the scope, `admin` for `?hard=true`",
+                &mut gen,
+            ),
+            error_of(404, "No such work", &mut gen),
+            error_of(409, "`?hard=true` on a work which has events", &mut gen),
+        ],
+    );
+    let retrieve_timeline = operation(
+        "The events of a work in chronological order",
+        vec![id_param()],
+        None,
+        vec![
+            response_of(
+                200,
+                "The timeline",
+                Some(schema_for::<WorkTimeline>(&mut gen)),
+            ),
+            error_of(404, "No such work", &mut gen),
+        ],
+    );
+    let search_work = operation(
+        "Search the works one page at a time",
+        search_params(&mut gen),
+        None,
+        vec![
+            response_of(
+                200,
+                "A page of works",
+                Some(schema_for::<WorkPage>(&mut gen)),
+            ),
+            error_of(400, "Unknown, repeated or invalid parameters", &mut gen),
+        ],
+    );
+    // the events are only nested in the timeline, documented on their own for the AMQP consumers
+    schema_for::<Event>(&mut gen);
+
+    json!({
+        "openapi": "3.0.3",
+        "info": {
+            "title": "pp_backend_api",
+            "description": "Create, track and search the works to do",
+            "version": env!("CARGO_PKG_VERSION"),
+        },
+        "paths": {
+            "/healthz": {"get": healthz},
+            "/readyz": {"get": readyz},
+            "/metrics": {"get": metrics},
+            "/openapi.json": {"get": openapi},
+            "/work": {"post": optional_body(secured(create_work, write, &mut gen))},
+            "/work/{id}": {
+                "get": secured(retrieve_work, read, &mut gen),
+                "put": secured(replace_work, write, &mut gen),
+                "patch": secured(patch_work, write, &mut gen),
+                "delete": secured(delete_work, "work:write, admin with `?hard=true`", &mut gen),
+            },
+            "/work/{id}/events": {"get": secured(retrieve_timeline, read, &mut gen)},
+            "/work/demands": {"post": secured(create_demand, write, &mut gen)},
+            "/work/demands/{tracking_id}": {"get": secured(retrieve_demand, read, &mut gen)},
+            "/work/search": {"get": secured(search_work, read, &mut gen)},
+        },
+        "components": {
+            "schemas": definitions(&mut gen),
+            "securitySchemes": {"bearer": {"type": "http", "scheme": "bearer"}},
+        },
+    })
+}
+
+// a reference to `T` in `#/components/schemas/`, inline for the primitive types
+fn schema_for<T: JsonSchema>(gen: &mut SchemaGenerator) -> Value {
+    serde_json::to_value(gen.subschema_for::<T>()).unwrap()
+}
+
+// the schemas referenced so far, made valid OpenAPI 3.0 (e.g. no `true` schema)
+fn definitions(gen: &mut SchemaGenerator) -> Map<String, Value> {
+    let mut definitions = gen.take_definitions();
+    for visitor in gen.visitors_mut() {
+        definitions
+            .values_mut()
+            .for_each(|schema: &mut Schema| visitor.visit_schema(schema));
+    }
+    definitions
+        .into_iter()
+        .map(|(name, schema)| (name, serde_json::to_value(schema).unwrap()))
+        .collect()
+}
+
+fn operation(
+    summary: &str,
+    parameters: Vec<Value>,
+    body: Option<Value>,
+    responses: Vec<(u16, Value)>,
+) -> Value {
+    let mut operation = json!({
+        "summary": summary,
+        "responses": responses
+            .into_iter()
+            .map(|(code, response)| (code.to_string(), response))
+            .collect::<Map<String, Value>>(),
+    });
+    if !parameters.is_empty() {
+        operation["parameters"] = Value::Array(parameters);
+    }
+    if let Some(schema) = body {
+        operation["requestBody"] = json!({
+            "required": true,
+            "content": {"application/json": {"schema": schema}},
+        });
+    }
+    operation
+}
+
+// the work routes need an API key with `scopes`, see `api::authorize`, and are rate limited
+fn secured(mut operation: Value, scopes: &str, gen: &mut SchemaGenerator) -> Value {
+    operation["security"] = json!([{"bearer": []}]);
+    operation["description"] = Value::String(format!("Needs the scope `{}`.", scopes));
+    for (code, response) in [
+        error_of(401, "Missing, unknown or revoked API key", gen),
+        error_of(403, "The API key lacks the scope", gen),
+        error_of(429, "Too many requests, see `Retry-After`", gen),
+    ] {
+        // the operation may say more, e.g. which scope is lacking
+        let responses = operation["responses"].as_object_mut().unwrap();
+        responses.entry(code.to_string()).or_insert(response);
+    }
+    operation
+}
+
+// a random work is created without a body
+fn optional_body(mut operation: Value) -> Value {
+    operation["requestBody"]["required"] = Value::Bool(false);
+    operation
+}
+
+fn response_of(code: u16, description: &str, schema: Option<Value>) -> (u16, Value) {
+    let mut response = json!({ "description": description });
+    if let Some(schema) = schema {
+        response["content"] = json!({"application/json": {"schema": schema}});
+    }
+    (code, response)
+}
+
+// every error has the same body, see `error::error_response`
+fn error_of(code: u16, description: &str, gen: &mut SchemaGenerator) -> (u16, Value) {
+    response_of(code, description, Some(schema_for::<ErrorBody>(gen)))
+}
+
+fn demand_accepted(gen: &mut SchemaGenerator) -> (u16, Value) {
+    let mut accepted = response_of(
+        202,
+        "Queued, to poll at `Location`",
+        Some(schema_for::<WorkDemandReceipt>(gen)),
+    );
+    accepted.1["headers"] = json!({"Location": {"schema": {"type": "string"}}});
+    accepted
+}
+
+fn update_responses(gen: &mut SchemaGenerator) -> Vec<(u16, Value)> {
+    vec![
+        response_of(200, "The work updated", Some(schema_for::<Work>(gen))),
+        error_of(400, "Missing or malformed body", gen),
+        error_of(404, "No such work", gen),
+        error_of(409, "Not allowed in the current status of the work", gen),
+        error_of(422, "Some fields are out of bounds", gen),
+    ]
+}
+
+fn id_param() -> Value {
+    json!({
+        "name": "id",
+        "in": "path",
+        "required": true,
+        "schema": {"type": "integer", "format": "int32"},
+    })
+}
+
+fn query_param(name: &str, description: &str, schema: Value) -> Value {
+    json!({
+        "name": name,
+        "in": "query",
+        "required": false,
+        "description": description,
+        "schema": schema,
+    })
+}
+
+// the parameters of `WorkQuery::from_query_string`
+fn search_params(gen: &mut SchemaGenerator) -> Vec<Value> {
+    let timestamp = json!({"type": "string", "description": "seconds since epoch or RFC 3339"});
+    let sort_values: Vec<String> = WorkSortField::ALL
+        .iter()
+        .flat_map(|f| vec![f.as_str().to_string(), format!("-{}", f.as_str())])
+        .collect();
+    WORK_QUERY_PARAMS
+        .iter()
+        .map(|&name| {
+            let (description, schema) = match name {
+                "work_code" => ("A prefix of the work code", json!({"type": "string"})),
+                "done" => ("Whether the status is final", json!({"type": "boolean"})),
+                "status" => ("The status", schema_for::<WorkStatus>(gen)),
+                "min_add_up_to" | "max_add_up_to" => {
+                    ("A bound of `add_up_to`, included", schema_for::<i32>(gen))
+                }
+                "created_after" | "created_before" => {
+                    ("A bound of `created_on`, excluded", timestamp.clone())
+                }
+                "sort" => (
+                    "The order, `-` first for the descending one",
+                    json!({"type": "string", "enum": sort_values, "default": "created_on"}),
+                ),
+                "limit" => (
+                    "The size of the page, capped",
+                    json!({
+                        "type": "integer",
+                        "minimum": 1,
+                        "maximum": MAX_PAGE_SIZE,
+                        "default": DEFAULT_PAGE_SIZE,
+                    }),
+                ),
+                "cursor" => (
+                    "The `next_cursor` of the previous page",
+                    json!({"type": "string"}),
+                ),
+                _ => ("", json!({"type": "string"})),
+            };
+            query_param(name, description, schema)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::document;
+    use crate::api::ROUTER;
+
+    #[test]
+    fn test_every_route_documented() {
+        // given the routes served, as OpenAPI paths and methods (`{id:int}` is `{id}`)
+        let routes: Vec<(String, String)> = ROUTER
+            .routes()
+            .into_iter()
+            .map(|(method, pattern)| (pattern.replace(":int}", "}"), method.to_string()))
+            .map(|(path, method)| (path, method.to_lowercase()))
+            .collect();
+
+        // when describing the API
+        let document = document();
+        let documented: Vec<(String, String)> = document["paths"]
+            .as_object()
+            .unwrap()
+            .iter()
+            .flat_map(|(path, item)| {
+                let methods = item.as_object().unwrap().keys();
+                methods.map(move |method| (path.clone(), method.clone()))
+            })
+            .collect();
+
+        // then every route is documented, and nothing else
+        for route in routes.iter() {
+            assert!(documented.contains(route), "{:?} is not documented", route);
+        }
+        for operation in documented.iter() {
+            assert!(routes.contains(operation), "{:?} is not served", operation);
+        }
+    }
+}
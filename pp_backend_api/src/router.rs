@@ -0,0 +1,230 @@
+use std::str::FromStr;
+
+use tiny_http::Method;
+
+use pp_lib::Error;
+
+// a part of a path pattern between two `/`
+#[derive(Debug)]
+enum Segment {
+    Literal(String),
+    // `{name}` matches any segment, `{name:int}` only digits
+    Param { name: String, digits: bool },
+}
+
+impl Segment {
+    fn parse(pattern: &str) -> Segment {
+        match pattern.strip_prefix('{').and_then(|p| p.strip_suffix('}')) {
+            Some(param) => match param.split_once(':') {
+                Some((name, "int")) => Segment::Param {
+                    name: String::from(name),
+                    digits: true,
+                },
+                Some((_, kind)) => panic!("Unknown kind of path parameter '{}'", kind),
+                None => Segment::Param {
+                    name: String::from(param),
+                    digits: false,
+                },
+            },
+            None => Segment::Literal(String::from(pattern)),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Route<T> {
+    method: Method,
+    pattern: String,
+    segments: Vec<Segment>,
+    name: &'static str,
+    endpoint: T,
+}
+
+impl<T> Route<T> {
+    // the path parameters when `parts` match the pattern
+    fn capture(&self, parts: &[&str]) -> Option<Vec<(String, String)>> {
+        if parts.len() != self.segments.len() {
+            return None;
+        }
+        let mut captured = Vec::new();
+        for (segment, part) in self.segments.iter().zip(parts) {
+            match segment {
+                Segment::Literal(literal) if literal == part => {}
+                Segment::Param { name, digits }
+                    if !part.is_empty()
+                        && (!digits || part.chars().all(|c| c.is_ascii_digit())) =>
+                {
+                    captured.push((name.clone(), part.to_string()))
+                }
+                _ => return None,
+            }
+        }
+        Some(captured)
+    }
+}
+
+/// The path parameters and the query string of a routed request.
+#[derive(Debug)]
+pub struct Params {
+    path: Vec<(String, String)>,
+    query: String,
+}
+
+impl Params {
+    /// The path parameter `name` parsed, `Error::Validation` when it does not parse.
+    pub fn get<P: FromStr>(&self, name: &str) -> Result<P, Error> {
+        let value = self
+            .path
+            .iter()
+            .find(|(param, _)| param == name)
+            .map(|(_, value)| value.as_str())
+            .ok_or_else(|| Error::Validation(format!("Missing {} in the path", name)))?;
+        value
+            .parse()
+            .map_err(|_| Error::Validation(format!("Invalid {} {}", name, value)))
+    }
+
+    /// Everything after the `?`, empty when none.
+    pub fn query(&self) -> &str {
+        self.query.as_str()
+    }
+
+    /// Whether `?<name>=true`, `false` when missing.
+    pub fn flag(&self, name: &str) -> Result<bool, Error> {
+        let value = self
+            .query
+            .split('&')
+            .filter_map(|pair| pair.split_once('='))
+            .find(|(param, _)| *param == name)
+            .map(|(_, value)| value);
+        match value {
+            None | Some("false") => Ok(false),
+            Some("true") => Ok(true),
+            Some(other) => Err(Error::Validation(format!(
+                "Invalid ?{}={}, expected true or false",
+                name, other
+            ))),
+        }
+    }
+}
+
+/// Where a request goes.
+#[derive(Debug)]
+pub enum Routed<'r, T> {
+    Found {
+        name: &'static str,
+        endpoint: &'r T,
+        params: Params,
+    },
+    // an `OPTIONS` request, answered with the methods of the path
+    Options {
+        allow: String,
+    },
+    // the path exists, not with this method
+    MethodNotAllowed {
+        allow: String,
+    },
+    NotFound,
+}
+
+/// The routes by method and path pattern, e.g. `/work/{id:int}/events`,
+/// the first one registered wins when several match.
+/// `HEAD` is served by the `GET` route and `OPTIONS` is answered for every path.
+#[derive(Debug)]
+pub struct Router<T> {
+    routes: Vec<Route<T>>,
+}
+
+impl<T> Default for Router<T> {
+    fn default() -> Router<T> {
+        Router { routes: Vec::new() }
+    }
+}
+
+impl<T> Router<T> {
+    pub fn new() -> Router<T> {
+        Router::default()
+    }
+
+    /// Serve `method` on `pattern` with `endpoint`, `name` is the label of its metrics.
+    pub fn route(mut self, method: Method, pattern: &str, name: &'static str, endpoint: T) -> Self {
+        let segments = split_path(pattern)
+            .into_iter()
+            .map(Segment::parse)
+            .collect();
+        self.routes.push(Route {
+            method,
+            pattern: String::from(pattern),
+            segments,
+            name,
+            endpoint,
+        });
+        self
+    }
+
+    /// The method and path pattern of every route, in the order they were registered.
+    pub fn routes(&self) -> Vec<(&Method, &str)> {
+        self.routes
+            .iter()
+            .map(|route| (&route.method, route.pattern.as_str()))
+            .collect()
+    }
+
+    pub fn find(&self, method: &Method, url: &str) -> Routed<'_, T> {
+        let (path, query) = url.split_once('?').unwrap_or((url, ""));
+        let parts = split_path(path);
+        let serves = |route: &Route<T>| {
+            route.method == *method || (*method == Method::Head && route.method == Method::Get)
+        };
+        let found = self
+            .routes
+            .iter()
+            .filter(|route| serves(route))
+            .find_map(|route| route.capture(&parts).map(|captured| (route, captured)));
+        match found {
+            Some((route, captured)) => Routed::Found {
+                name: route.name,
+                endpoint: &route.endpoint,
+                params: Params {
+                    path: captured,
+                    query: String::from(query),
+                },
+            },
+            None => match self.allow(&parts) {
+                None => Routed::NotFound,
+                Some(allow) if *method == Method::Options => Routed::Options { allow },
+                Some(allow) => Routed::MethodNotAllowed { allow },
+            },
+        }
+    }
+
+    // the methods served on the path, e.g. `GET, HEAD, OPTIONS`, `None` for an unknown path
+    fn allow(&self, parts: &[&str]) -> Option<String> {
+        let mut methods: Vec<String> = Vec::new();
+        for route in self.routes.iter().filter(|r| r.capture(parts).is_some()) {
+            let mut add = |method: &Method| {
+                let method = method.to_string();
+                if !methods.contains(&method) {
+                    methods.push(method);
+                }
+            };
+            add(&route.method);
+            if route.method == Method::Get {
+                add(&Method::Head);
+            }
+        }
+        if methods.is_empty() {
+            return None;
+        }
+        let options = Method::Options.to_string();
+        if !methods.contains(&options) {
+            methods.push(options);
+        }
+        Some(methods.join(", "))
+    }
+}
+
+// `/work/1/` and `/work/1` are the same path
+fn split_path(path: &str) -> Vec<&str> {
+    path.trim_matches('/').split('/').collect()
+}
@@ -0,0 +1,73 @@
+mod server_db_tests {
+    use std::io::{Read, Write};
+    use std::net::TcpStream;
+    use std::process::{Child, Command, Stdio};
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    use pp_lib::service::pool::CONNECTION_TIMEOUT;
+
+    const PORT: u16 = 3975;
+
+    // the server is killed when the test ends, even on a failure
+    struct Server(Child);
+
+    impl Drop for Server {
+        fn drop(&mut self) {
+            let _ = self.0.kill();
+            let _ = self.0.wait();
+        }
+    }
+
+    // 2 workers sharing a single DB connection, a single work request a minute per address
+    fn start_server() -> Server {
+        let child = Command::new(env!("CARGO_BIN_EXE_pp_backend_api"))
+            .env("PP_STORE", "postgres")
+            .env("PP_API_PORT", PORT.to_string())
+            .env("PP_API_AUTH", "false")
+            .env("PP_API_WORKERS", "2")
+            .env("PP_API_DB_CONNECTIONS", "1")
+            .env("PP_API_RATE_LIMITS", "addr=1/60")
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .unwrap();
+        let server = Server(child);
+
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while TcpStream::connect(("127.0.0.1", PORT)).is_err() {
+            assert!(Instant::now() < deadline, "the server did not start");
+            thread::sleep(Duration::from_millis(50));
+        }
+        server
+    }
+
+    #[test]
+    fn test_addr_limit_before_the_pool() {
+        // given a running server
+        let _server = start_server();
+        // given its only DB connection held by a slow client, which never sends its body
+        let mut stalled = TcpStream::connect(("127.0.0.1", PORT)).unwrap();
+        write!(
+            stalled,
+            "POST /work HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\nContent-Length: 2048\r\n\r\n"
+        )
+        .unwrap();
+        thread::sleep(Duration::from_millis(200));
+
+        // when the same address sends one more work request
+        let started = Instant::now();
+        let mut stream = TcpStream::connect(("127.0.0.1", PORT)).unwrap();
+        write!(
+            stream,
+            "GET /work/search HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n"
+        )
+        .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+
+        // then it is refused at once, without waiting for a DB connection
+        assert!(response.starts_with("HTTP/1.1 429"), "{}", response);
+        assert!(started.elapsed() < CONNECTION_TIMEOUT / 2);
+    }
+}
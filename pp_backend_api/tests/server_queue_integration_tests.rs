@@ -0,0 +1,101 @@
+mod server_queue_tests {
+    use std::io::{Read, Write};
+    use std::net::TcpStream;
+    use std::process::{Child, Command, Stdio};
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    use serde_json::Value;
+
+    use pp_lib::service::queue;
+
+    const PORT: u16 = 3979;
+
+    // the server is killed when the test ends, even on a failure
+    struct Server(Child);
+
+    impl Drop for Server {
+        fn drop(&mut self) {
+            let _ = self.0.kill();
+            let _ = self.0.wait();
+        }
+    }
+
+    fn start_server() -> Server {
+        let child = Command::new(env!("CARGO_BIN_EXE_pp_backend_api"))
+            .env("PP_STORE", "memory")
+            .env("PP_API_PORT", PORT.to_string())
+            .env("PP_API_AUTH", "false")
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .unwrap();
+        let server = Server(child);
+
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while TcpStream::connect(("127.0.0.1", PORT)).is_err() {
+            assert!(Instant::now() < deadline, "the server did not start");
+            thread::sleep(Duration::from_millis(50));
+        }
+        server
+    }
+
+    // the HTTP status code and the body
+    fn request(method: &str, path: &str, body: &str) -> (u16, String) {
+        let mut stream = TcpStream::connect(("127.0.0.1", PORT)).unwrap();
+        write!(
+            stream,
+            "{} {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\nContent-Length: {}\r\n\r\n{}",
+            method,
+            path,
+            body.len(),
+            body
+        )
+        .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        let status = response
+            .split_whitespace()
+            .nth(1)
+            .and_then(|code| code.parse().ok())
+            .unwrap_or(0);
+        let body = response.split("\r\n\r\n").nth(1).unwrap_or("").to_string();
+        (status, body)
+    }
+
+    #[test]
+    fn test_work_demands_queue() {
+        // given a running server and an empty queue
+        let _server = start_server();
+
+        // when sending a demand, then it is accepted with a tracking id
+        let (status, body) = request("POST", "/work/demands", r#"{"add_up_to": 3}"#);
+        assert_eq!(202, status, "{}", body);
+        let receipt: Value = serde_json::from_str(body.as_str()).unwrap();
+        let tracking_id = receipt["tracking_id"].as_str().unwrap().to_string();
+        let location = format!("/work/demands/{}", tracking_id);
+
+        // when polling it while it is in the queue, then it is still accepted
+        let (status, _) = request("GET", location.as_str(), "");
+        assert_eq!(202, status);
+
+        // when consuming the queue, then the demand carries its tracking id
+        let demands = queue::consume_amqp_queue(1).unwrap();
+        assert_eq!(Some(&tracking_id), demands[0].tracking_id.as_ref());
+
+        // given its work no longer queued (the consumer needs the DB, the server runs in memory)
+        let search = format!("/work/search?work_code={}", tracking_id);
+        let (_, body) = request("GET", search.as_str(), "");
+        let page: Value = serde_json::from_str(body.as_str()).unwrap();
+        let work_path = format!("/work/{}", page["items"][0]["id"]);
+        let (status, body) = request("PATCH", work_path.as_str(), r#"{"status": "cancelled"}"#);
+        assert_eq!(200, status, "{}", body);
+
+        // when polling it, then the work is there
+        let (status, body) = request("GET", location.as_str(), "");
+        assert_eq!(200, status);
+        let work: Value = serde_json::from_str(body.as_str()).unwrap();
+        assert_eq!(tracking_id.as_str(), work["work_code"]);
+        assert_eq!("cancelled", work["status"]);
+    }
+}
@@ -0,0 +1,548 @@
+mod server_tests {
+    use std::env;
+    use std::fs::{self, File};
+    use std::io::{Read, Write};
+    use std::net::TcpStream;
+    use std::process::{Child, Command, Stdio};
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    use serde_json::Value;
+
+    use pp_lib::query::WORK_QUERY_PARAMS;
+
+    const WORKERS: usize = 4;
+
+    // the server is killed when the test ends, even on a failure
+    struct Server(Child);
+
+    impl Drop for Server {
+        fn drop(&mut self) {
+            let _ = self.0.kill();
+            let _ = self.0.wait();
+        }
+    }
+
+    // every test listens on its own `port`, the tests run in parallel
+    fn start_server(port: u16) -> Server {
+        start_server_with(port, &[], Stdio::null())
+    }
+
+    // `envs` on top of the defaults, `logs` where the logs go
+    fn start_server_with(port: u16, envs: &[(&str, &str)], logs: Stdio) -> Server {
+        let child = Command::new(env!("CARGO_BIN_EXE_pp_backend_api"))
+            .env("PP_STORE", "memory")
+            .env("PP_API_PORT", port.to_string())
+            .env("PP_API_WORKERS", WORKERS.to_string())
+            .env("PP_API_SHUTDOWN_SECS", "5")
+            // a memory store has no API key to present
+            .env("PP_API_AUTH", "false")
+            .envs(envs.iter().copied())
+            .stdout(Stdio::null())
+            .stderr(logs)
+            .spawn()
+            .unwrap();
+        let server = Server(child);
+
+        // wait for the port to be open
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while TcpStream::connect(addr(port)).is_err() {
+            assert!(Instant::now() < deadline, "the server did not start");
+            thread::sleep(Duration::from_millis(50));
+        }
+        server
+    }
+
+    fn addr(port: u16) -> String {
+        format!("127.0.0.1:{}", port)
+    }
+
+    fn status_code(response: &str) -> u16 {
+        response
+            .split_whitespace()
+            .nth(1)
+            .and_then(|code| code.parse().ok())
+            .unwrap_or(0)
+    }
+
+    // the HTTP status code, the whole response and how long it took to get it
+    fn get(port: u16, path: &str) -> (u16, String, Duration) {
+        get_with(port, path, "")
+    }
+
+    // `headers` are extra header lines, each ending with `\r\n`
+    fn get_with(port: u16, path: &str, headers: &str) -> (u16, String, Duration) {
+        let started = Instant::now();
+        let mut stream = TcpStream::connect(addr(port)).unwrap();
+        write!(
+            stream,
+            "GET {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n{}\r\n",
+            path, headers
+        )
+        .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        (status_code(response.as_str()), response, started.elapsed())
+    }
+
+    // the HTTP status code and the whole response
+    fn post(port: u16, path: &str, body: &str) -> (u16, String) {
+        request(port, "POST", path, body.as_bytes())
+    }
+
+    fn request(port: u16, method: &str, path: &str, body: &[u8]) -> (u16, String) {
+        let mut stream = TcpStream::connect(addr(port)).unwrap();
+        write!(
+            stream,
+            "{} {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\nContent-Length: {}\r\n\r\n",
+            method,
+            path,
+            body.len()
+        )
+        .unwrap();
+        stream.write_all(body).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        (status_code(response.as_str()), response)
+    }
+
+    // a request whose body is not sent yet, a worker stays busy reading it
+    // (tiny_http hands over the bodies larger than 1KB while they are still arriving)
+    fn stalled_post(port: u16, body: &str) -> TcpStream {
+        let mut stream = TcpStream::connect(addr(port)).unwrap();
+        write!(
+            stream,
+            "POST /work HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\nContent-Length: {}\r\n\r\n",
+            body.len()
+        )
+        .unwrap();
+        stream
+    }
+
+    fn new_work_body() -> String {
+        format!("{:<2048}", "{\"add_up_to\": 3}")
+    }
+
+    #[test]
+    fn test_concurrent_requests() {
+        // given a running server
+        let _server = start_server(3999);
+        // given all its workers but one stuck with slow clients
+        let body = new_work_body();
+        let stalled: Vec<TcpStream> = (0..WORKERS - 1)
+            .map(|_| stalled_post(3999, body.as_str()))
+            .collect();
+        thread::sleep(Duration::from_millis(200));
+
+        // when many clients search at the same time
+        let clients: Vec<_> = (0..20)
+            .map(|_| thread::spawn(|| get(3999, "/work/search")))
+            .collect();
+
+        // then they are all served by the free worker, without waiting for the slow clients
+        for client in clients {
+            let (status, _, elapsed) = client.join().unwrap();
+            assert_eq!(200, status);
+            assert!(elapsed < Duration::from_secs(2), "served in {:?}", elapsed);
+        }
+        drop(stalled);
+    }
+
+    #[test]
+    fn test_graceful_shutdown() {
+        // given a running server in the middle of a request
+        let mut server = start_server(3998);
+        let body = new_work_body();
+        let mut in_flight = stalled_post(3998, body.as_str());
+        thread::sleep(Duration::from_millis(200));
+
+        // when it is asked to stop
+        let killed = Command::new("kill")
+            .args(["-TERM", server.0.id().to_string().as_str()])
+            .status()
+            .unwrap();
+        assert!(killed.success());
+        thread::sleep(Duration::from_millis(300));
+
+        // then it does not take new connections
+        assert!(TcpStream::connect(addr(3998)).is_err());
+
+        // then the request in flight is still served
+        in_flight.write_all(body.as_bytes()).unwrap();
+        let mut response = String::new();
+        in_flight.read_to_string(&mut response).unwrap();
+        assert_eq!(200, status_code(response.as_str()), "{}", response);
+
+        // then the server exits cleanly
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let status = loop {
+            if let Some(status) = server.0.try_wait().unwrap() {
+                break status;
+            }
+            assert!(Instant::now() < deadline, "the server did not stop");
+            thread::sleep(Duration::from_millis(50));
+        };
+        assert!(status.success());
+    }
+
+    #[test]
+    fn test_health_checks() {
+        // given a running server
+        let _server = start_server(3997);
+
+        // when asking whether it is alive, then it is
+        let (status, response, _) = get(3997, "/healthz");
+        assert_eq!(200, status);
+        assert!(response.ends_with(r#"{"status":"alive"}"#), "{}", response);
+
+        // when asking whether it is ready, then its store is up
+        let (status, response, _) = get(3997, "/readyz");
+        assert_eq!(200, status);
+        assert!(
+            response.ends_with(r#"{"status":"ready","checks":{"memory":{"status":"up"}}}"#),
+            "{}",
+            response
+        );
+
+        // given a server checking a broker it cannot reach
+        let _server = start_server_with(
+            3976,
+            &[
+                ("PP_API_CHECK_AMQP", "true"),
+                ("DOCKER_AMQP_HOST", "broker.invalid"),
+            ],
+            Stdio::null(),
+        );
+        // when asking whether it is ready, then it is not, without telling why
+        let (status, response, _) = get(3976, "/readyz");
+        assert_eq!(503, status);
+        assert!(
+            response.ends_with(
+                r#"{"status":"not_ready","checks":{"amqp":{"status":"down","error":"unavailable"},"memory":{"status":"up"}}}"#
+            ),
+            "{}",
+            response
+        );
+    }
+
+    #[test]
+    fn test_metrics() {
+        // given a running server which served a request, and one with a made up method
+        let _server = start_server(3996);
+        get(3996, "/healthz");
+        request(3996, "BREW-COFFEE", "/healthz", b"");
+
+        // when scraping its metrics
+        let (status, response, _) = get(3996, "/metrics");
+
+        // then the request is counted and timed, by route
+        assert_eq!(200, status);
+        assert!(response
+            .contains(r#"pp_http_requests_total{method="GET",route="healthz",status="200"} 1"#));
+        assert!(response
+            .contains(r#"pp_http_request_duration_seconds_count{method="GET",route="healthz"} 1"#));
+        // then the made up method is not a label of its own
+        assert!(!response.contains("BREW-COFFEE"));
+        assert!(response.contains(r#"method="other",route="method_not_allowed""#));
+    }
+
+    #[test]
+    fn test_openapi() {
+        // given a running server
+        let _server = start_server(3995);
+
+        // when asking for its OpenAPI document
+        let (status, response, _) = get(3995, "/openapi.json");
+        assert_eq!(200, status);
+        let body = response.split("\r\n\r\n").nth(1).unwrap();
+        let document: Value = serde_json::from_str(body).unwrap();
+
+        // then every search parameter is documented (the routes are checked by `openapi::tests`)
+        let search_params: Vec<&str> = document["paths"]["/work/search"]["get"]["parameters"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|param| param["name"].as_str().unwrap())
+            .collect();
+        assert_eq!(WORK_QUERY_PARAMS.to_vec(), search_params);
+        // then the payloads are described by their Rust types
+        let schemas = &document["components"]["schemas"];
+        for name in &["Work", "Event", "WorkTimeline", "WorkPage", "ErrorBody"] {
+            assert!(schemas.get(name).is_some(), "{} is not documented", name);
+        }
+        assert_eq!(
+            "integer",
+            schemas["Work"]["properties"]["created_on"]["type"]
+        );
+    }
+
+    #[test]
+    fn test_auth() {
+        // given a running server enforcing the API keys, with its logs in a file
+        let log_path = env::temp_dir().join("pp_backend_api_test_auth.log");
+        let logs = File::create(&log_path).unwrap();
+        let mut server = start_server_with(3994, &[("PP_API_AUTH", "true")], Stdio::from(logs));
+
+        // when calling a work route without a key, then it is refused
+        let (status, response, _) = get(3994, "/work/search");
+        assert_eq!(401, status);
+        assert!(
+            response.contains("WWW-Authenticate: Bearer"),
+            "{}",
+            response
+        );
+        assert!(response.contains(r#""http_code":401"#), "{}", response);
+        // when presenting an unknown key, then it is refused
+        let (status, _, _) = get_with(
+            3994,
+            "/work/1",
+            "Authorization: Bearer pp_not-a-real-key\r\n",
+        );
+        assert_eq!(401, status);
+        // when calling the routes that are not about the works, then no key is needed
+        for path in &["/healthz", "/readyz", "/openapi.json", "/metrics"] {
+            let (status, _, _) = get(3994, path);
+            assert_eq!(200, status, "{}", path);
+        }
+
+        // then the key is never logged
+        server.0.kill().unwrap();
+        server.0.wait().unwrap();
+        let logs = fs::read_to_string(&log_path).unwrap();
+        assert!(logs.contains("<redacted>"), "{}", logs);
+        assert!(!logs.contains("pp_not-a-real-key"), "{}", logs);
+    }
+
+    #[test]
+    fn test_rate_limit() {
+        // given a running server allowing 2 searches a minute per client
+        let _server = start_server_with(
+            3993,
+            &[("PP_API_RATE_LIMITS", "search_work=2/60")],
+            Stdio::null(),
+        );
+
+        // when searching twice, then the client is told what is left
+        let (status, response, _) = get(3993, "/work/search");
+        assert_eq!(200, status);
+        assert!(response.contains("RateLimit-Limit: 2"), "{}", response);
+        assert!(response.contains("RateLimit-Remaining: 1"), "{}", response);
+        let (status, response, _) = get(3993, "/work/search");
+        assert_eq!(200, status);
+        assert!(response.contains("RateLimit-Remaining: 0"), "{}", response);
+
+        // when searching once more, then it is refused until a token is back
+        let (status, response, _) = get(3993, "/work/search");
+        assert_eq!(429, status);
+        assert!(response.contains("Retry-After: 30"), "{}", response);
+        assert!(response.contains("RateLimit-Reset: 60"), "{}", response);
+        assert!(response.contains(r#""http_code":429"#), "{}", response);
+
+        // then the routes without a limit are still served
+        let (status, _, _) = get(3993, "/work/1");
+        assert_eq!(404, status);
+
+        // given a server allowing 2 work requests a minute per remote address
+        let _server = start_server_with(
+            3978,
+            &[("PP_API_AUTH", "true"), ("PP_API_RATE_LIMITS", "addr=2/60")],
+            Stdio::null(),
+        );
+        // when calling it without a key, then it is refused
+        for _ in 0..2 {
+            let (status, _, _) = get(3978, "/work/search");
+            assert_eq!(401, status);
+        }
+        // when calling it once more, then it is limited before the key is checked
+        let (status, response, _) = get(3978, "/work/search");
+        assert_eq!(429, status);
+        assert!(response.contains("Retry-After: 30"), "{}", response);
+    }
+
+    #[test]
+    fn test_daily_work_quota() {
+        // given a running server allowing 1 work a day per client
+        let _server = start_server_with(3992, &[("PP_API_DAILY_WORK_QUOTA", "1")], Stdio::null());
+
+        // when a work is not created, then it does not count
+        let (status, _) = post(3992, "/work", "{not json");
+        assert_eq!(400, status);
+        let (status, _) = post(3992, "/work", r#"{"add_up_to": 3}"#);
+        assert_eq!(200, status);
+
+        // when creating one more, then it is refused until tomorrow
+        let (status, response) = post(3992, "/work", r#"{"add_up_to": 3}"#);
+        assert_eq!(429, status);
+        assert!(response.contains("Retry-After: "), "{}", response);
+        assert!(response.contains(r#""http_code":429"#), "{}", response);
+        // then the other routes are still served
+        let (status, _, _) = get(3992, "/work/search");
+        assert_eq!(200, status);
+
+        // when the quota is not a number, then the server does not start
+        let status = Command::new(env!("CARGO_BIN_EXE_pp_backend_api"))
+            .env("PP_STORE", "memory")
+            .env("PP_API_PORT", "3977")
+            .env("PP_API_DAILY_WORK_QUOTA", "lots")
+            .stderr(Stdio::null())
+            .status()
+            .unwrap();
+        assert_eq!(Some(1), status.code());
+    }
+
+    #[test]
+    fn test_routing() {
+        // given a running server
+        let _server = start_server(3991);
+
+        // when calling a route with a method it does not serve, then it says which ones it does
+        let (status, response) = request(3991, "POST", "/work/search", b"");
+        assert_eq!(405, status);
+        assert!(
+            response.contains("Allow: GET, HEAD, OPTIONS"),
+            "{}",
+            response
+        );
+        assert!(response.contains(r#""http_code":405"#), "{}", response);
+        let (status, response) = request(3991, "GET", "/work", b"");
+        assert_eq!(405, status);
+        assert!(response.contains("Allow: POST, OPTIONS"), "{}", response);
+
+        // when asking for the options of a path, then the methods are listed
+        let (status, response) = request(3991, "OPTIONS", "/work/1000/", b"");
+        assert_eq!(204, status);
+        assert!(
+            response.contains("Allow: GET, HEAD, PUT, PATCH, DELETE, OPTIONS"),
+            "{}",
+            response
+        );
+
+        // when asking for the headers only, then the GET route answers without a body
+        let (status, response) = request(3991, "HEAD", "/healthz", b"");
+        assert_eq!(200, status);
+        assert!(response.ends_with("\r\n\r\n"), "{}", response);
+
+        // when the path is unknown or its parameter of the wrong type, then it is not found
+        for path in &["/nowhere", "/work/foo", "/work/1000/events/1"] {
+            let (status, _, _) = get(3991, path);
+            assert_eq!(404, status, "{}", path);
+        }
+        // when a path parameter does not fit its type, then it is refused
+        let (status, response, _) = get(3991, "/work/99999999999");
+        assert_eq!(400, status);
+        assert!(response.contains("Invalid id"), "{}", response);
+    }
+
+    #[test]
+    fn test_malformed_input() {
+        // given a running server with a single worker, any crash would leave it unable to serve
+        let _server = start_server_with(3990, &[("PP_API_WORKERS", "1")], Stdio::null());
+        let (status, _) = post(3990, "/work", r#"{"add_up_to": 3}"#);
+        assert_eq!(200, status);
+
+        // when sending all sorts of malformed input, then each one gets a JSON error
+        let bad_requests: Vec<(&str, &str, &[u8], u16)> = vec![
+            ("GET", "/work/99999999999", b"", 400),
+            ("GET", "/work/2147483648/events", b"", 400),
+            ("GET", "/work/2147483647", b"", 404),
+            ("GET", "/work/0/events", b"", 404),
+            ("PUT", "/work/99999999999", br#"{"add_up_to": 3}"#, 400),
+            ("PATCH", "/work/1", b"{", 400),
+            ("PATCH", "/work/1", br#"{"status": "exploded"}"#, 400),
+            ("PATCH", "/work/1", br#"{"status": "succeeded"}"#, 422),
+            ("DELETE", "/work/99999999999", b"", 400),
+            ("DELETE", "/work/1?hard=maybe", b"", 400),
+            ("POST", "/work", b"", 400),
+            ("POST", "/work", b"[]", 400),
+            ("POST", "/work", b"\xff\xfe\x00", 400),
+            ("POST", "/work", br#"{"add_up_to": "ten"}"#, 400),
+            ("POST", "/work", br#"{"add_up_to": 99999999999}"#, 400),
+            ("POST", "/work", br#"{"add_up_to": 1e400}"#, 400),
+            ("POST", "/work", br#"{"add_up_to": 0}"#, 422),
+            ("POST", "/work?random=maybe", b"", 400),
+            ("GET", "/work/search?limit=-1", b"", 400),
+            ("GET", "/work/search?limit=99999999999", b"", 400),
+            (
+                "GET",
+                "/work/search?created_after=99999999999999999",
+                b"",
+                400,
+            ),
+            ("GET", "/work/search?created_before=not-a-date", b"", 400),
+            ("GET", "/work/search?min_add_up_to=1.5", b"", 400),
+            ("GET", "/work/search?cursor=%FF%FE", b"", 400),
+            (
+                "GET",
+                "/work/search?cursor=OTk5OTk5OTk5OTk5OTk5OTk6MDox",
+                b"",
+                400,
+            ),
+            ("GET", "/work/search?status=%FF", b"", 400),
+            ("GET", "/work/search?sort=sideways", b"", 400),
+            ("GET", "/work/search?limit=1&limit=2", b"", 400),
+        ];
+        for (method, path, body, expected) in bad_requests {
+            let (status, response) = request(3990, method, path, body);
+            assert_eq!(expected, status, "{} {}: {}", method, path, response);
+            let body = response.split("\r\n\r\n").nth(1).unwrap();
+            let error: Value = serde_json::from_str(body).unwrap();
+            assert_eq!(expected, error["http_code"], "{} {}", method, path);
+        }
+
+        // then the server is still up and serving
+        let (status, _, _) = get(3990, "/work/search");
+        assert_eq!(200, status);
+        let (status, _, _) = get(3990, "/healthz");
+        assert_eq!(200, status);
+    }
+
+    #[test]
+    fn test_work_demands() {
+        // given a running server without a broker (see `server_queue_integration_tests`)
+        let _server = start_server_with(
+            3989,
+            &[("DOCKER_AMQP_HOST", "broker.invalid")],
+            Stdio::null(),
+        );
+
+        // when sending a demand out of bounds, then it is refused before reaching the queue
+        let (status, response) = post(3989, "/work/demands", r#"{"add_up_to": 0}"#);
+        assert_eq!(422, status, "{}", response);
+        let (status, response) = post(
+            3989,
+            "/work/demands",
+            r#"{"add_up_to": 3, "tracking_id": "demand-mine"}"#,
+        );
+        assert_eq!(422, status, "{}", response);
+        let (status, _) = post(3989, "/work/demands", "");
+        assert_eq!(400, status);
+
+        // when a client picks the code of a demand for its work, then it is refused
+        let (status, response) = post(
+            3989,
+            "/work",
+            r#"{"add_up_to": 3, "work_code": "demand-abc123"}"#,
+        );
+        assert_eq!(422, status, "{}", response);
+        assert!(response.contains(r#""field":"work_code""#), "{}", response);
+
+        // when polling a tracking id never issued, then it is not found
+        let (status, _, _) = get(3989, "/work/demands/demand-abc123");
+        assert_eq!(404, status);
+
+        // given no broker, when sending a demand, then it is unavailable and leaves no work behind
+        let (status, response) = post(3989, "/work/demands", r#"{"add_up_to": 3}"#);
+        assert_eq!(503, status, "{}", response);
+        let (status, response, _) = get(3989, "/work/search?work_code=demand");
+        assert_eq!(200, status);
+        assert!(
+            response.ends_with(r#"{"items":[],"next_cursor":null}"#),
+            "{}",
+            response
+        );
+
+        // when polling something else than a tracking id, then it is not found
+        let (status, _, _) = get(3989, "/work/demands/api-abc123");
+        assert_eq!(404, status);
+    }
+}
@@ -0,0 +1,163 @@
+use std::fmt;
+use std::str::FromStr;
+
+use chrono::serde::ts_seconds_option;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::error::Error;
+use crate::service::store::WorkStore;
+
+// every minted key starts with it, handy to spot a leaked key
+pub const KEY_PREFIX: &str = "pp_";
+// 256 bits drawn from the OS, the hash of such a key needs no salt
+const KEY_BYTES: usize = 32;
+
+/// What an API key is allowed to do, `Admin` is allowed everything.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq, Hash, Serialize)]
+pub enum Scope {
+    #[serde(rename = "work:read")]
+    WorkRead,
+    #[serde(rename = "work:write")]
+    WorkWrite,
+    #[serde(rename = "admin")]
+    Admin,
+}
+
+impl Scope {
+    pub const ALL: &'static [Scope] = &[Scope::WorkRead, Scope::WorkWrite, Scope::Admin];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Scope::WorkRead => "work:read",
+            Scope::WorkWrite => "work:write",
+            Scope::Admin => "admin",
+        }
+    }
+
+    /// The scopes of a comma separated list, e.g. `work:read,work:write`.
+    pub fn parse_list(names: &str) -> Result<Vec<Scope>, Error> {
+        let mut scopes: Vec<Scope> = Vec::new();
+        for name in names.split(',').map(str::trim).filter(|n| !n.is_empty()) {
+            let scope: Scope = name.parse()?;
+            if !scopes.contains(&scope) {
+                scopes.push(scope);
+            }
+        }
+        match scopes.is_empty() {
+            true => Err(Error::Validation(String::from(
+                "At least one scope is needed",
+            ))),
+            false => Ok(scopes),
+        }
+    }
+}
+
+impl fmt::Display for Scope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl FromStr for Scope {
+    type Err = Error;
+
+    fn from_str(name: &str) -> Result<Scope, Error> {
+        Scope::ALL
+            .iter()
+            .find(|s| s.as_str() == name)
+            .copied()
+            .ok_or_else(|| Error::Validation(format!("Unknown scope '{}'", name)))
+    }
+}
+
+/// An API key as stored, only the hash of the secret is kept (see `hash_key`).
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+pub struct ApiKey {
+    pub id: i32,
+    // who or what the key was minted for, e.g. `ci`
+    pub name: String,
+    pub scopes: Vec<Scope>,
+    #[serde(with = "ts_seconds_option")]
+    pub created_on: Option<DateTime<Utc>>,
+    // `None` while the key is usable
+    #[serde(with = "ts_seconds_option")]
+    pub revoked_on: Option<DateTime<Utc>>,
+}
+
+impl ApiKey {
+    pub fn allows(&self, scope: Scope) -> bool {
+        self.scopes.contains(&scope) || self.scopes.contains(&Scope::Admin)
+    }
+
+    // `Error::Forbidden` when the key lacks `scope`
+    pub fn require(&self, scope: Scope) -> Result<(), Error> {
+        match self.allows(scope) {
+            true => Ok(()),
+            false => Err(Error::Forbidden(format!(
+                "The API key {} lacks the scope {}",
+                self.name, scope
+            ))),
+        }
+    }
+}
+
+/// The SHA-256 hex digest stored in place of the `key`.
+pub fn hash_key(key: &str) -> String {
+    format!("{:x}", Sha256::digest(key.as_bytes()))
+}
+
+/// Draw a new secret key, it is shown once to whoever mints it and never stored.
+pub fn generate_key() -> Result<String, Error> {
+    let mut bytes = [0u8; KEY_BYTES];
+    getrandom::getrandom(&mut bytes)
+        .map_err(|err| Error::Config(format!("Not able to draw a random key: {}", err)))?;
+    Ok(format!(
+        "{}{}",
+        KEY_PREFIX,
+        base64::encode_config(bytes, base64::URL_SAFE_NO_PAD)
+    ))
+}
+
+/// Mint a key with `scopes` for `name`: the stored key and its secret.
+pub fn mint_key(
+    store: &mut dyn WorkStore,
+    name: &str,
+    scopes: &[Scope],
+) -> Result<(ApiKey, String), Error> {
+    if name.is_empty() || name.len() > 50 {
+        return Err(Error::Validation(String::from(
+            "The name of the key must be 1 to 50 characters long",
+        )));
+    }
+    let secret = generate_key()?;
+    let key = store.create_api_key(name, hash_key(secret.as_str()).as_str(), scopes)?;
+    Ok((key, secret))
+}
+
+/// The key presented in an `Authorization: Bearer <key>` header,
+/// `Error::Unauthorized` when missing, unknown or revoked.
+pub fn authenticate(
+    store: &mut dyn WorkStore,
+    authorization: Option<&str>,
+) -> Result<ApiKey, Error> {
+    let header = authorization.ok_or_else(|| {
+        Error::Unauthorized(String::from("Missing header Authorization: Bearer <key>"))
+    })?;
+    let secret = match header.trim().split_once(' ') {
+        Some((scheme, secret)) if scheme.eq_ignore_ascii_case("bearer") => secret.trim(),
+        _ => {
+            return Err(Error::Unauthorized(String::from(
+                "Expected header Authorization: Bearer <key>",
+            )))
+        }
+    };
+    match store.find_api_key(hash_key(secret).as_str()) {
+        Ok(key) => Ok(key),
+        Err(Error::NotFound(_)) => Err(Error::Unauthorized(String::from(
+            "Unknown or revoked API key",
+        ))),
+        Err(err) => Err(err),
+    }
+}
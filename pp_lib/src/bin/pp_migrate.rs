@@ -0,0 +1,44 @@
+use std::env;
+use std::process;
+
+use env_logger::Env;
+
+use pp_lib::{factory, migration};
+
+// Apply the pending DB migrations:
+//   cargo run --bin pp_migrate
+// Only check the DB schema version, exit code 1 when behind:
+//   cargo run --bin pp_migrate -- --check
+fn main() {
+    let env = Env::default()
+        .filter_or("MY_LOG_LEVEL", "info")
+        .write_style_or("MY_LOG_STYLE", "always");
+    env_logger::init_from_env(env);
+
+    let check_only = env::args().any(|arg| arg == "--check");
+    let mut db = match factory::db_client() {
+        Ok(db) => db,
+        Err(err) => {
+            log::error!("{}", err);
+            process::exit(1);
+        }
+    };
+
+    let res = match check_only {
+        true => migration::check(&mut db).map(|version| {
+            log::info!("The DB schema is up to date at version {}", version);
+        }),
+        false => pp_lib::migrate(&mut db).map(|counter| {
+            log::info!(
+                "Done, {} migrations applied, the DB schema is at version {}",
+                counter,
+                migration::latest_version()
+            );
+        }),
+    };
+
+    if let Err(err) = res {
+        log::error!("{}", err);
+        process::exit(1);
+    }
+}
@@ -0,0 +1,133 @@
+use std::error;
+use std::fmt;
+
+use schemars::JsonSchema;
+use serde::Serialize;
+
+/// A rejected input field, e.g. `add_up_to` out of range.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, JsonSchema)]
+pub struct FieldError {
+    pub field: String,
+    pub message: String,
+}
+
+impl FieldError {
+    pub fn new(field: &str, message: &str) -> FieldError {
+        FieldError {
+            field: String::from(field),
+            message: String::from(message),
+        }
+    }
+}
+
+/// Everything that can go wrong in `pp_lib`.
+///
+/// The variants wrapping a third party failure keep it as `source`,
+/// the binaries decide how to surface them (e.g. HTTP status codes in `pp_backend_api`).
+#[derive(Debug)]
+pub enum Error {
+    /// The requested row does not exist.
+    NotFound(String),
+    /// The operation clashes with the current state (duplicated key, rows still referenced...).
+    Conflict(String),
+    /// The input is not acceptable.
+    Validation(String),
+    /// The input is well formed but some of its fields are not acceptable.
+    InvalidFields(Vec<FieldError>),
+    /// The caller did not prove who it is (missing, unknown or revoked API key).
+    Unauthorized(String),
+    /// The caller is known but not allowed to do this.
+    Forbidden(String),
+    /// The caller went over its rate limit or quota, it may try again later.
+    TooManyRequests(String),
+    Database {
+        message: String,
+        source: Option<postgres::Error>,
+    },
+    Broker {
+        message: String,
+        // boxed, `amiquip::Error` would make every `Result` in the crate very large
+        source: Option<Box<amiquip::Error>>,
+    },
+    Serialization {
+        message: String,
+        source: serde_json::Error,
+    },
+    /// The environment or the deployment is not usable (e.g. the DB schema lags behind).
+    Config(String),
+}
+
+impl Error {
+    pub fn database(message: &str, source: postgres::Error) -> Error {
+        Error::Database {
+            message: String::from(message),
+            source: Some(source),
+        }
+    }
+
+    pub fn broker(message: &str, source: amiquip::Error) -> Error {
+        Error::Broker {
+            message: String::from(message),
+            source: Some(Box::new(source)),
+        }
+    }
+
+    pub fn serialization(message: &str, source: serde_json::Error) -> Error {
+        Error::Serialization {
+            message: String::from(message),
+            source,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::NotFound(message)
+            | Error::Conflict(message)
+            | Error::Validation(message)
+            | Error::Unauthorized(message)
+            | Error::Forbidden(message)
+            | Error::TooManyRequests(message)
+            | Error::Config(message) => write!(f, "{}", message),
+            Error::InvalidFields(fields) => {
+                let fields: Vec<String> = fields
+                    .iter()
+                    .map(|fe| format!("{}: {}", fe.field, fe.message))
+                    .collect();
+                write!(f, "Invalid fields, {}", fields.join("; "))
+            }
+            Error::Database {
+                message,
+                source: Some(source),
+            } => write!(f, "{}, the error: {}", message, source),
+            Error::Broker {
+                message,
+                source: Some(source),
+            } => write!(f, "{}, the error: {}", message, source),
+            Error::Database { message, .. } | Error::Broker { message, .. } => {
+                write!(f, "{}", message)
+            }
+            Error::Serialization { message, source } => {
+                write!(f, "{}, the error: {}", message, source)
+            }
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Error::Database {
+                source: Some(source),
+                ..
+            } => Some(source),
+            Error::Broker {
+                source: Some(source),
+                ..
+            } => Some(source.as_ref()),
+            Error::Serialization { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
@@ -3,67 +3,162 @@ use std::env;
 use std::iter::repeat_with;
 
 use amiquip::Connection;
-use chrono::{SubsecRound, Utc};
+use chrono::{DateTime, SubsecRound, Utc};
 use fastrand;
+use lazy_static::lazy_static;
 use postgres::{Client, NoTls};
 
+use super::error::Error;
 use super::model;
 use super::service::db;
+use super::service::memory::MemoryStore;
+use super::service::pool::StorePool;
 use super::service::queue;
+use super::service::store::WorkStore;
 
-pub fn db_client() -> Client {
+fn db_connection_str() -> String {
     match env::var("DOCKER_DB_HOST") {
         Ok(docker_db_host) => {
             let conn_str = db::DB_CONNECTION_STR.replace("localhost", docker_db_host.as_str());
             log::info!("Using docker network DB connection string: {}", conn_str);
-            let db = Client::connect(conn_str.as_str(), NoTls).unwrap();
-            return db;
+            conn_str
         }
         Err(err) => {
             log::info!("Using default DB connection string, error: {}", err);
-            let db = Client::connect(db::DB_CONNECTION_STR, NoTls).unwrap();
-            return db;
+            String::from(db::DB_CONNECTION_STR)
         }
     }
 }
 
+pub fn db_client() -> Result<Client, Error> {
+    Client::connect(db_connection_str().as_str(), NoTls)
+        .map_err(|err| Error::database("Not able to connect to the DB", err))
+}
+
+lazy_static! {
+    // every in-memory store of the process is a handle on this one
+    static ref MEMORY_STORE: MemoryStore = MemoryStore::new();
+}
+
+enum StoreKind {
+    Postgres,
+    Memory,
+}
+
+fn store_kind() -> Result<StoreKind, Error> {
+    match env::var("PP_STORE").as_deref() {
+        Ok("memory") => Ok(StoreKind::Memory),
+        Ok("postgres") | Err(_) => Ok(StoreKind::Postgres),
+        Ok(other) => Err(Error::Config(format!(
+            "Unknown PP_STORE value '{}', expected 'postgres' or 'memory'",
+            other
+        ))),
+    }
+}
+
+/// Pick the storage backend from the `PP_STORE` environment variable:
+/// `memory` for the in-process store (the same for the whole process), `postgres` (the default) for the DB.
+pub fn work_store() -> Result<Box<dyn WorkStore + Send>, Error> {
+    match store_kind()? {
+        StoreKind::Memory => {
+            log::info!("Using the in-memory work store");
+            Ok(Box::new(MEMORY_STORE.clone()))
+        }
+        StoreKind::Postgres => Ok(Box::new(db_client()?)),
+    }
+}
+
+/// Same as `work_store` for a short-lived process (e.g. a CLI command),
+/// `Error::Config` for the in-memory store: what it writes would be gone when the process exits.
+pub fn durable_work_store() -> Result<Box<dyn WorkStore + Send>, Error> {
+    match store_kind()? {
+        StoreKind::Memory => Err(Error::Config(String::from(
+            "PP_STORE=memory keeps nothing once the process exits, use the DB",
+        ))),
+        StoreKind::Postgres => work_store(),
+    }
+}
+
+/// Same as `work_store` for concurrent workers, with at most `size` DB connections.
+pub fn work_store_pool(size: u32) -> Result<StorePool, Error> {
+    match store_kind()? {
+        StoreKind::Memory => {
+            log::info!("Using the in-memory work store");
+            Ok(StorePool::Memory(MEMORY_STORE.clone()))
+        }
+        StoreKind::Postgres => StorePool::postgres(db_connection_str().as_str(), size),
+    }
+}
+
 pub fn rand_alphanumeric() -> String {
     let length = 10;
-    return repeat_with(fastrand::alphanumeric).take(length).collect();
+    repeat_with(fastrand::alphanumeric).take(length).collect()
 }
 
 pub fn rand_alphanumeric_any(length: usize) -> String {
-    return repeat_with(fastrand::alphanumeric).take(length).collect();
+    repeat_with(fastrand::alphanumeric).take(length).collect()
+}
+
+/// Replace the random suffix of a `work_code` like `api-bjq8euwsEA`,
+/// keeping the prefix before the last `-`.
+pub fn reroll_work_code(work_code: &str) -> String {
+    match work_code.rfind('-') {
+        Some(idx) => format!("{}-{}", &work_code[..idx], rand_alphanumeric()),
+        None => format!("{}-{}", work_code, rand_alphanumeric()),
+    }
 }
 
 pub fn rand_num() -> i32 {
-    return fastrand::i32(1..100);
+    fastrand::i32(1..100)
 }
 
 // https://github.com/jgallagher/amiquip/blob/master/examples/work_queues_new_task.rs
 // https://github.com/jgallagher/amiquip/blob/master/examples/work_queues_worker.rs
-pub fn amqp_connection() -> amiquip::Connection {
-    let connection = Connection::insecure_open(queue::QUEUE_CONNECTION_STR).unwrap();
+fn amqp_connection_str() -> String {
+    match env::var("DOCKER_AMQP_HOST") {
+        Ok(docker_amqp_host) => {
+            let conn_str =
+                queue::QUEUE_CONNECTION_STR.replace("localhost", docker_amqp_host.as_str());
+            log::info!("Using docker network AMQP connection string: {}", conn_str);
+            conn_str
+        }
+        Err(_) => String::from(queue::QUEUE_CONNECTION_STR),
+    }
+}
+
+pub fn amqp_connection() -> Result<amiquip::Connection, Error> {
+    let connection = Connection::insecure_open(amqp_connection_str().as_str())
+        .map_err(|err| Error::broker("Not able to connect to the AMQP broker", err))?;
     let props = connection.server_properties();
     log::info!(
         "Supplying AMQP connection for cluster {:?} version {:?}",
         props.get("cluster_name"),
         props.get("version")
     );
-    connection
+    Ok(connection)
 }
 
-pub fn new_work(work_code: &str, add_up_to: i32) -> model::Work {
+/// The current time, as precise as the DB can store it.
+pub fn now() -> DateTime<Utc> {
     // Postgres TIMESTAMPTZ has 6 decimals
     // chrono DateTime<Utc> has 9 decimals...
-    let now = Utc::now().round_subsecs(6);
+    Utc::now().round_subsecs(6)
+}
+
+pub fn new_work(work_code: &str, add_up_to: i32) -> model::Work {
+    let now = now();
     model::Work {
         id: 0,
         work_code: String::from(work_code),
-        add_up_to: add_up_to,
-        done: false,
+        add_up_to,
+        status: model::WorkStatus::Pending,
         updated_on: Some(now),
         created_on: Some(now),
+        result: None,
+        started_at: None,
+        finished_at: None,
+        duration_ms: None,
+        metadata: None,
     }
 }
 
@@ -71,45 +166,100 @@ pub fn generate_random_work(work_code_prefix: &str) -> model::Work {
     let w_id: String = format!("{}-{}", work_code_prefix, rand_alphanumeric());
     let work_code: &str = w_id.as_str();
     let work_add_up_to: i32 = rand_num();
-    let work: model::Work = new_work(work_code, work_add_up_to);
-    work
+    new_work(work_code, work_add_up_to)
+}
+
+/// The work asked for by a client (see `NewWork::validate`),
+/// its `work_code` is random (`<work_code_prefix>-xxx`) unless the client chose one.
+pub fn map_new_work(demand: model::NewWork, work_code_prefix: &str) -> model::Work {
+    let work_code = match demand.work_code {
+        Some(work_code) => work_code,
+        None => format!("{}-{}", work_code_prefix, rand_alphanumeric()),
+    };
+    model::Work {
+        metadata: demand.metadata,
+        ..new_work(work_code.as_str(), demand.add_up_to)
+    }
 }
 
 pub fn generate_random_work_demand() -> model::WorkDemand {
     let work_add_up_to: i32 = rand_num();
-    let wd = model::WorkDemand {
+    model::WorkDemand {
         add_up_to: work_add_up_to,
         done: false,
+        tracking_id: None,
+    }
+}
+
+/// The demand with a new `tracking_id` (`demand-xxx`), the `work_code` of its work,
+/// along with that tracking id.
+pub fn track_work_demand(wd: model::WorkDemand) -> (model::WorkDemand, String) {
+    let tracking_id = format!("{}-{}", model::DEMAND_PREFIX, rand_alphanumeric());
+    let tracked = model::WorkDemand {
+        tracking_id: Some(tracking_id.clone()),
+        ..wd
     };
-    wd
+    (tracked, tracking_id)
+}
+
+/// Whether `tracking_id` may come from `track_work_demand`.
+pub fn is_tracking_id(tracking_id: &str) -> bool {
+    match tracking_id.strip_prefix(model::DEMAND_PREFIX) {
+        Some(suffix) => {
+            suffix.len() > 1
+                && suffix.starts_with('-')
+                && suffix[1..].chars().all(|c| c.is_ascii_alphanumeric())
+        }
+        None => false,
+    }
 }
 
 /// This is for consumers e.g. AMQP consumers.
-/// We take the `actor_prefix` and append a random string
+/// The `work_code` is the `tracking_id` of the demand, if any,
+/// otherwise we take the `actor_prefix` and append a random string
 /// so we have a unique `work_code` identifier.
 pub fn map_to_work(wd: model::WorkDemand, actor_prefix: &str) -> model::Work {
-    let work_code: String = format!("{}-{}", actor_prefix, rand_alphanumeric());
-    let now = Utc::now().round_subsecs(6);
-    let w = model::Work {
+    let work_code: String = match wd.tracking_id {
+        Some(tracking_id) => tracking_id,
+        None => format!("{}-{}", actor_prefix, rand_alphanumeric()),
+    };
+    let now = now();
+    model::Work {
         id: -1,
-        work_code: work_code,
+        work_code,
         add_up_to: wd.add_up_to,
-        done: wd.done,
+        // it has been pulled from the queue
+        status: model::WorkStatus::Queued,
         updated_on: Some(now),
         created_on: Some(now),
-    };
-    w
+        result: None,
+        started_at: None,
+        finished_at: None,
+        duration_ms: None,
+        metadata: None,
+    }
 }
 
-pub fn new_event(work_code: &str, variable: &str, value: &str) -> model::Event {
-    // Postgres TIMESTAMPTZ has 6 decimals
-    // chrono DateTime<Utc> has 9 decimals...
-    let now = Utc::now().round_subsecs(6);
+pub fn new_event(
+    work_code: &str,
+    variable: model::EventVariable,
+    value: Option<model::EventValue>,
+) -> model::Event {
+    let now = now();
     model::Event {
         id: 0,
         work_code: String::from(work_code),
-        variable: String::from(variable),
-        value: String::from(value),
+        variable,
+        value,
         created_on: Some(now),
     }
 }
+
+/// The computation started at `started_at` is over (now) and gave `value`.
+pub fn new_work_result(value: i64, started_at: DateTime<Utc>) -> model::WorkResult {
+    model::WorkResult {
+        value,
+        started_at,
+        finished_at: now(),
+    }
+}
@@ -1,3 +1,11 @@
+pub mod auth;
+pub mod error;
 pub mod factory;
+pub mod metrics;
+pub mod migration;
 pub mod model;
+pub mod query;
 pub mod service;
+
+pub use error::Error;
+pub use migration::migrate;
@@ -0,0 +1,148 @@
+use std::io::Cursor;
+use std::thread::{spawn, JoinHandle};
+use std::time::Instant;
+
+use lazy_static::lazy_static;
+use prometheus::{
+    exponential_buckets, register_histogram, register_histogram_vec, register_int_counter_vec,
+    register_int_gauge, Encoder, Histogram, HistogramVec, IntCounterVec, IntGauge, TextEncoder,
+};
+use tiny_http::{Header, Response, Server, StatusCode};
+
+use crate::error::Error;
+
+// Every binary exposes the metrics of the default Prometheus registry in the text format,
+// the ones it never touches are simply not reported.
+lazy_static! {
+    // `pp_backend_api`, see `api::serve_routes`
+    pub static ref HTTP_REQUESTS_TOTAL: IntCounterVec = register_int_counter_vec!(
+        "pp_http_requests_total",
+        "HTTP requests served, by route and status code",
+        &["method", "route", "status"]
+    )
+    .unwrap();
+    pub static ref HTTP_REQUEST_SECONDS: HistogramVec = register_histogram_vec!(
+        "pp_http_request_duration_seconds",
+        "Time spent serving the HTTP requests, by route",
+        &["method", "route"]
+    )
+    .unwrap();
+
+    // `service::db`, through the `WorkStore` of a Postgres client
+    pub static ref DB_CALL_SECONDS: HistogramVec = register_histogram_vec!(
+        "pp_db_call_duration_seconds",
+        "Time spent in the DB calls, by work store operation",
+        &["operation"]
+    )
+    .unwrap();
+
+    // `service::queue`
+    pub static ref QUEUE_MESSAGES_TOTAL: IntCounterVec = register_int_counter_vec!(
+        "pp_queue_messages_total",
+        "AMQP messages published or consumed, `failed` when not sent or not parsed",
+        &["operation", "outcome"]
+    )
+    .unwrap();
+    pub static ref QUEUE_FAILURES_TOTAL: IntCounterVec = register_int_counter_vec!(
+        "pp_queue_failures_total",
+        "AMQP publish or consume calls that failed (connection, channel...)",
+        &["operation"]
+    )
+    .unwrap();
+
+    // `task_consumer`
+    pub static ref COMPUTE_SECONDS: Histogram = register_histogram!(
+        "pp_compute_duration_seconds",
+        "Time spent computing a work",
+        // from 0.1s to about 2 hours, the upper bound of `add_up_to` takes about 3 hours
+        exponential_buckets(0.1, 4.0, 9).unwrap()
+    )
+    .unwrap();
+    pub static ref WORKS_IN_FLIGHT: IntGauge = register_int_gauge!(
+        "pp_works_in_flight",
+        "Works taken over by the consumer and not stored yet"
+    )
+    .unwrap();
+}
+
+/// Run `call` and observe how long it took as the DB `operation`.
+pub fn time_db<T>(operation: &str, call: impl FnOnce() -> T) -> T {
+    let _timer = DB_CALL_SECONDS
+        .with_label_values(&[operation])
+        .start_timer();
+    call()
+}
+
+// the methods a client can make up are all counted as `other`, the labels stay few
+fn method_label(method: &str) -> &'static str {
+    match method {
+        "GET" => "GET",
+        "HEAD" => "HEAD",
+        "POST" => "POST",
+        "PUT" => "PUT",
+        "PATCH" => "PATCH",
+        "DELETE" => "DELETE",
+        "OPTIONS" => "OPTIONS",
+        _ => "other",
+    }
+}
+
+/// Count an HTTP request once served.
+pub fn observe_http(method: &str, route: &str, status: u16, started: Instant) {
+    let method = method_label(method);
+    HTTP_REQUESTS_TOTAL
+        .with_label_values(&[method, route, status.to_string().as_str()])
+        .inc();
+    HTTP_REQUEST_SECONDS
+        .with_label_values(&[method, route])
+        .observe(started.elapsed().as_secs_f64());
+}
+
+/// Count an AMQP message, `operation` is `publish` or `consume`.
+pub fn count_message(operation: &str, ok: bool) {
+    let outcome = if ok { "ok" } else { "failed" };
+    QUEUE_MESSAGES_TOTAL
+        .with_label_values(&[operation, outcome])
+        .inc();
+}
+
+/// The metrics in the Prometheus text format.
+pub fn render() -> String {
+    let mut buffer = Vec::new();
+    let encoder = TextEncoder::new();
+    // writing into a `Vec` only fails on invalid metrics, i.e. a bug
+    encoder.encode(&prometheus::gather(), &mut buffer).unwrap();
+    String::from_utf8(buffer).unwrap()
+}
+
+/// The `GET /metrics` response.
+pub fn response() -> Response<Cursor<Vec<u8>>> {
+    let content_type =
+        Header::from_bytes("Content-Type", TextEncoder::new().format_type()).unwrap();
+    Response::from_string(render())
+        .with_header(content_type)
+        .with_status_code(StatusCode(200))
+}
+
+/// Serve `GET /metrics` on `port` from a background thread,
+/// for the binaries without an HTTP API (`task_producer`, `task_consumer`).
+pub fn serve(port: &str) -> Result<JoinHandle<()>, Error> {
+    let server = Server::http(format!("0.0.0.0:{}", port)).map_err(|err| {
+        Error::Config(format!(
+            "Not able to serve the metrics on port {}: {}",
+            port, err
+        ))
+    })?;
+    log::info!("Serving the metrics on port {}", port);
+    Ok(spawn(move || {
+        for rq in server.incoming_requests() {
+            let res = match rq.url() {
+                "/metrics" => response(),
+                _ => Response::from_string("").with_status_code(StatusCode(404)),
+            };
+            if let Err(err) = rq.respond(res) {
+                log::error!("Failed to respond to the metrics request: {}", err);
+            }
+        }
+    }))
+}
@@ -0,0 +1,169 @@
+use log;
+use postgres::{Client, GenericClient};
+
+use crate::error::Error;
+
+/// A forward-only schema change, applied at most once per database.
+pub struct Migration {
+    pub version: i32,
+    pub name: &'static str,
+    pub sql: &'static str,
+}
+
+/// Every migration embedded in the binary, sorted by `version`.
+/// Never edit an applied migration, append a new one instead.
+pub const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        name: "create_works_and_events",
+        sql: include_str!("migrations/V001__create_works_and_events.sql"),
+    },
+    Migration {
+        version: 2,
+        name: "work_code_unique_and_event_foreign_key",
+        sql: include_str!("migrations/V002__work_code_unique_and_event_foreign_key.sql"),
+    },
+    Migration {
+        version: 3,
+        name: "work_result_columns",
+        sql: include_str!("migrations/V003__work_result_columns.sql"),
+    },
+    Migration {
+        version: 4,
+        name: "typed_event_values",
+        sql: include_str!("migrations/V004__typed_event_values.sql"),
+    },
+    Migration {
+        version: 5,
+        name: "work_status",
+        sql: include_str!("migrations/V005__work_status.sql"),
+    },
+    Migration {
+        version: 6,
+        name: "work_metadata",
+        sql: include_str!("migrations/V006__work_metadata.sql"),
+    },
+    Migration {
+        version: 7,
+        name: "work_soft_delete",
+        sql: include_str!("migrations/V007__work_soft_delete.sql"),
+    },
+    Migration {
+        version: 8,
+        name: "work_keyset_index",
+        sql: include_str!("migrations/V008__work_keyset_index.sql"),
+    },
+    Migration {
+        version: 9,
+        name: "api_keys",
+        sql: include_str!("migrations/V009__api_keys.sql"),
+    },
+    Migration {
+        version: 10,
+        name: "work_quotas",
+        sql: include_str!("migrations/V010__work_quotas.sql"),
+    },
+];
+
+// arbitrary key for `pg_advisory_xact_lock`, it serializes concurrent runners
+const MIGRATION_LOCK_ID: i64 = 7_010_001;
+
+/// The version the binary expects the database to be at.
+pub fn latest_version() -> i32 {
+    MIGRATIONS.last().map_or(0, |m| m.version)
+}
+
+/// The highest version applied to the database, 0 for a blank database.
+pub fn current_version<C: GenericClient>(db: &mut C) -> Result<i32, Error> {
+    let row = db
+        .query_one("SELECT to_regclass('schema_migrations') IS NOT NULL;", &[])
+        .map_err(|err| Error::database("Not able to look for the schema_migrations table", err))?;
+    let tracked: bool = row.get(0);
+    if !tracked {
+        return Ok(0);
+    }
+    let row = db
+        .query_one(
+            "SELECT COALESCE(MAX(version), 0) FROM schema_migrations;",
+            &[],
+        )
+        .map_err(|err| Error::database("Not able to read the schema version", err))?;
+    Ok(row.get(0))
+}
+
+/// Apply the pending migrations in a single transaction,
+/// returns how many of them have been applied (0 when up to date).
+pub fn migrate(db: &mut Client) -> Result<usize, Error> {
+    let mut tx = db
+        .transaction()
+        .map_err(|err| Error::database("Not able to start the migration transaction", err))?;
+    tx.execute("SELECT pg_advisory_xact_lock($1);", &[&MIGRATION_LOCK_ID])
+        .map_err(|err| Error::database("Not able to lock the migrations", err))?;
+    tx.batch_execute(
+        "
+        CREATE TABLE IF NOT EXISTS schema_migrations (
+            version    INT PRIMARY KEY,
+            name       VARCHAR ( 100 ) NOT NULL,
+            applied_on TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+        );
+        ",
+    )
+    .map_err(|err| Error::database("Not able to create the schema_migrations table", err))?;
+
+    let applied: Vec<i32> = tx
+        .query("SELECT version FROM schema_migrations;", &[])
+        .map_err(|err| Error::database("Not able to read the applied migrations", err))?
+        .iter()
+        .map(|row| row.get("version"))
+        .collect();
+
+    let mut counter = 0;
+    for migration in MIGRATIONS.iter().filter(|m| !applied.contains(&m.version)) {
+        log::info!(
+            "Applying migration V{:03} {}",
+            migration.version,
+            migration.name
+        );
+        tx.batch_execute(migration.sql).map_err(|err| {
+            Error::database(
+                format!("Not able to apply migration V{:03}", migration.version).as_str(),
+                err,
+            )
+        })?;
+        tx.execute(
+            "INSERT INTO schema_migrations (version, name) VALUES ($1, $2);",
+            &[&migration.version, &migration.name],
+        )
+        .map_err(|err| Error::database("Not able to record the migration", err))?;
+        counter += 1;
+    }
+
+    tx.commit()
+        .map_err(|err| Error::database("Not able to commit the migrations", err))?;
+    log::info!(
+        "Applied {} migrations, the schema is at version {}",
+        counter,
+        latest_version()
+    );
+    Ok(counter)
+}
+
+/// Fail fast at startup when the database lags behind the binary.
+pub fn check<C: GenericClient>(db: &mut C) -> Result<i32, Error> {
+    let current = current_version(db)?;
+    let latest = latest_version();
+    if current < latest {
+        return Err(Error::Config(format!(
+            "The DB schema is at version {} but version {} is required, run the migrations (pp_migrate)",
+            current, latest
+        )));
+    }
+    if current > latest {
+        log::warn!(
+            "The DB schema is at version {}, newer than the version {} known by this binary",
+            current,
+            latest
+        );
+    }
+    Ok(current)
+}
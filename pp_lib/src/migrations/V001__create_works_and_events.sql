@@ -1,6 +1,5 @@
--- SELECT session_user AS sess_user, current_database() AS curr_db;
+-- `IF NOT EXISTS` adopts the databases created by the former `schema_handmade.sql`
 
-DROP TABLE IF EXISTS works;
 CREATE TABLE IF NOT EXISTS works (
 	id         SERIAL PRIMARY KEY,
 	work_code  VARCHAR ( 50 ) NOT NULL,
@@ -10,8 +9,6 @@ CREATE TABLE IF NOT EXISTS works (
 	created_on TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
 );
 
-
-DROP TABLE IF EXISTS events;
 CREATE TABLE IF NOT EXISTS events (
 	id         SERIAL PRIMARY KEY,
 	work_code  VARCHAR ( 50 ) NOT NULL,  -- TODO perhaps foreign key to `works` table
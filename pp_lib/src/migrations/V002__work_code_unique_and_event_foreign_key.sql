@@ -0,0 +1,41 @@
+-- `work_code` is the natural key of a work, events point to it
+
+-- rename the (unlikely) duplicated codes, the oldest row keeps the original one:
+-- `<code>-<id>` cut to fit `VARCHAR(50)`, `<code>-<id>-<n>` when that one is taken too
+DO $$
+DECLARE
+	dup       RECORD;
+	suffix    TEXT;
+	candidate TEXT;
+	attempt   INT;
+BEGIN
+	FOR dup IN
+		SELECT id, work_code FROM works
+		WHERE id NOT IN (SELECT MIN(id) FROM works GROUP BY work_code)
+		ORDER BY id
+	LOOP
+		attempt := 0;
+		LOOP
+			suffix := '-' || dup.id || CASE WHEN attempt > 0 THEN '-' || attempt ELSE '' END;
+			candidate := left(dup.work_code, 50 - length(suffix)) || suffix;
+			EXIT WHEN NOT EXISTS (SELECT 1 FROM works WHERE work_code = candidate);
+			attempt := attempt + 1;
+		END LOOP;
+		UPDATE works SET work_code = candidate WHERE id = dup.id;
+	END LOOP;
+END
+$$;
+
+ALTER TABLE works ADD CONSTRAINT works_work_code_key UNIQUE (work_code);
+
+-- keep the orphan events by giving them a placeholder work (`add_up_to` 0)
+INSERT INTO works (work_code, add_up_to, done)
+SELECT DISTINCT e.work_code, 0, FALSE
+FROM events e
+WHERE NOT EXISTS (SELECT 1 FROM works w WHERE w.work_code = e.work_code);
+
+-- a work with events cannot be deleted, delete its events first
+ALTER TABLE events ADD CONSTRAINT events_work_code_fkey
+	FOREIGN KEY (work_code) REFERENCES works (work_code) ON DELETE RESTRICT;
+
+CREATE INDEX IF NOT EXISTS events_work_code_idx ON events (work_code);
@@ -0,0 +1,24 @@
+-- the outcome of the computation, NULL until the work is done
+
+ALTER TABLE works
+	ADD COLUMN IF NOT EXISTS result      BIGINT,      -- rust type i64
+	ADD COLUMN IF NOT EXISTS started_at  TIMESTAMPTZ,
+	ADD COLUMN IF NOT EXISTS finished_at TIMESTAMPTZ,
+	ADD COLUMN IF NOT EXISTS duration_ms BIGINT;
+
+-- backfill the works already done from their events
+UPDATE works w
+SET result = e.value::BIGINT
+FROM events e
+WHERE e.work_code = w.work_code
+	AND e.variable = 'compute/result'
+	AND e.value ~ '^-?[0-9]{1,18}$';
+
+UPDATE works w
+SET started_at  = (SELECT MIN(e.created_on) FROM events e WHERE e.work_code = w.work_code AND e.variable = 'compute/start'),
+	finished_at = (SELECT MAX(e.created_on) FROM events e WHERE e.work_code = w.work_code AND e.variable = 'compute/stop')
+WHERE w.done;
+
+UPDATE works
+SET duration_ms = (EXTRACT(EPOCH FROM finished_at - started_at) * 1000)::BIGINT
+WHERE started_at IS NOT NULL AND finished_at IS NOT NULL;
@@ -0,0 +1,17 @@
+-- `events.value` becomes a typed JSON payload, see `model::EventValue`
+-- e.g. {"type": "integer", "value": 10}, NULL when the event carries no value
+
+ALTER TABLE events ADD COLUMN typed_value JSONB;
+
+UPDATE events
+SET typed_value = CASE
+	WHEN value = '' THEN NULL
+	WHEN value ~ '^-?[0-9]{1,18}$' THEN jsonb_build_object('type', 'integer', 'value', value::BIGINT)
+	WHEN value ~ '^-?[0-9]+\.[0-9]+$' THEN jsonb_build_object('type', 'float', 'value', value::DOUBLE PRECISION)
+	WHEN value IN ('true', 'false') THEN jsonb_build_object('type', 'bool', 'value', value::BOOLEAN)
+	ELSE jsonb_build_object('type', 'text', 'value', value)
+END;
+
+ALTER TABLE events DROP COLUMN value;
+ALTER TABLE events RENAME COLUMN typed_value TO value;
+
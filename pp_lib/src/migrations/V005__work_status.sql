@@ -0,0 +1,12 @@
+-- `works.done` becomes `works.status`, see `model::WorkStatus`
+
+ALTER TABLE works ADD COLUMN status VARCHAR ( 20 ) NOT NULL DEFAULT 'pending';
+
+UPDATE works SET status = 'succeeded' WHERE done;
+
+ALTER TABLE works ADD CONSTRAINT works_status_check
+	CHECK (status IN ('pending', 'queued', 'running', 'succeeded', 'failed', 'cancelled'));
+
+ALTER TABLE works DROP COLUMN done;
+
+CREATE INDEX works_status_idx ON works (status);
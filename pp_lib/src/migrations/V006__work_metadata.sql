@@ -0,0 +1,3 @@
+-- free form JSON object supplied by the client on `POST /work`
+
+ALTER TABLE works ADD COLUMN IF NOT EXISTS metadata JSONB;
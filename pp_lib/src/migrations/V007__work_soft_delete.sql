@@ -0,0 +1,3 @@
+-- soft deleted works are hidden from the clients, the row and its events stay
+
+ALTER TABLE works ADD COLUMN IF NOT EXISTS deleted_on TIMESTAMPTZ;
@@ -0,0 +1,3 @@
+-- the search pages through the works in the `(created_on, id)` order
+
+CREATE INDEX IF NOT EXISTS works_created_on_id_idx ON works (created_on, id);
@@ -0,0 +1,10 @@
+-- the API keys of `pp_backend_api`, only the SHA-256 of the secret is stored (see `auth::hash_key`)
+
+CREATE TABLE api_keys (
+	id         SERIAL PRIMARY KEY,
+	name       VARCHAR ( 50 ) NOT NULL,
+	key_hash   CHAR ( 64 ) UNIQUE NOT NULL,
+	scopes     VARCHAR ( 20 ) [] NOT NULL, -- see `auth::Scope`
+	created_on TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+	revoked_on TIMESTAMPTZ                  -- NULL while the key is usable
+);
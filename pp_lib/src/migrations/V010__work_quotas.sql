@@ -0,0 +1,8 @@
+-- how many works each client of `pp_backend_api` created per day (UTC), see `WorkStore::take_work_quota`
+
+CREATE TABLE work_quotas (
+	client     VARCHAR ( 100 ) NOT NULL, -- `key:<api key id>` or `addr:<remote address>`
+	day        DATE NOT NULL,
+	created    INT NOT NULL DEFAULT 0,
+	PRIMARY KEY (client, day)
+);
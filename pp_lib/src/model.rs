@@ -1,45 +1,562 @@
 use chrono::{DateTime, Utc};
 // TODO custom serialize/deserialize: https://serde.rs/custom-date-format.html
 use chrono::serde::ts_seconds_option;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+use crate::error::{Error, FieldError};
 
 // derive "Debug", otherwise when calling unwrap:
-// ^^^^^^ method cannot be called on `Result<Work, pp_lib::model::Error>` due to unsatisfied trait bounds
+// ^^^^^^ method cannot be called on `Result<Work, pp_lib::Error>` due to unsatisfied trait bounds
 
-#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize, JsonSchema)]
 pub struct Work {
     pub id: i32,
     pub work_code: String,
     pub add_up_to: i32,
-    pub done: bool,
+    pub status: WorkStatus,
     #[serde(with = "ts_seconds_option")]
+    #[schemars(with = "Option<i64>", description = "Seconds since epoch")]
     pub created_on: Option<DateTime<Utc>>,
     #[serde(with = "ts_seconds_option")]
+    #[schemars(with = "Option<i64>", description = "Seconds since epoch")]
     pub updated_on: Option<DateTime<Utc>>,
+    // the outcome of the computation, `None` until the work has succeeded
+    pub result: Option<i64>,
+    #[serde(with = "ts_seconds_option")]
+    #[schemars(with = "Option<i64>", description = "Seconds since epoch")]
+    pub started_at: Option<DateTime<Utc>>,
+    #[serde(with = "ts_seconds_option")]
+    #[schemars(with = "Option<i64>", description = "Seconds since epoch")]
+    pub finished_at: Option<DateTime<Utc>>,
+    pub duration_ms: Option<i64>,
+    // free form JSON object supplied by the client
+    pub metadata: Option<serde_json::Value>,
+}
+
+pub const MIN_ADD_UP_TO: i32 = 1;
+pub const MAX_ADD_UP_TO: i32 = 100_000;
+// the `works.work_code` column is a `VARCHAR ( 50 )`
+pub const MAX_WORK_CODE_LEN: usize = 50;
+pub const MAX_METADATA_BYTES: usize = 4096;
+// the works of the demands sent over HTTP are coded `demand-xxx`, their tracking id
+pub const DEMAND_PREFIX: &str = "demand";
+
+/// What a client asks for when creating a work, e.g. the JSON body of `POST /work`:
+///
+/// ```json
+/// {"add_up_to": 10, "work_code": "api-mine", "metadata": {"owner": "me"}}
+/// ```
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct NewWork {
+    pub add_up_to: i32,
+    // a random code is drawn when missing
+    #[serde(default)]
+    pub work_code: Option<String>,
+    #[serde(default)]
+    pub metadata: Option<serde_json::Value>,
+}
+
+fn check_add_up_to(add_up_to: i32, errors: &mut Vec<FieldError>) {
+    if !(MIN_ADD_UP_TO..=MAX_ADD_UP_TO).contains(&add_up_to) {
+        errors.push(FieldError::new(
+            "add_up_to",
+            format!("must be between {} and {}", MIN_ADD_UP_TO, MAX_ADD_UP_TO).as_str(),
+        ));
+    }
+}
+
+fn check_work_code(work_code: &str, errors: &mut Vec<FieldError>) {
+    if work_code.is_empty() || work_code.len() > MAX_WORK_CODE_LEN {
+        errors.push(FieldError::new(
+            "work_code",
+            format!("must be 1 to {} characters long", MAX_WORK_CODE_LEN).as_str(),
+        ));
+    } else if !work_code
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '-')
+    {
+        errors.push(FieldError::new(
+            "work_code",
+            "must only contain ASCII letters, digits and '-'",
+        ));
+    } else if work_code.starts_with(format!("{}-", DEMAND_PREFIX).as_str()) {
+        // a client could otherwise answer the polls of someone else's demand
+        errors.push(FieldError::new(
+            "work_code",
+            format!("must not start with '{}-', it is reserved", DEMAND_PREFIX).as_str(),
+        ));
+    }
+}
+
+fn check_metadata(metadata: &serde_json::Value, errors: &mut Vec<FieldError>) {
+    if !metadata.is_object() {
+        errors.push(FieldError::new("metadata", "must be a JSON object"));
+    } else if metadata.to_string().len() > MAX_METADATA_BYTES {
+        errors.push(FieldError::new(
+            "metadata",
+            format!("must be at most {} bytes", MAX_METADATA_BYTES).as_str(),
+        ));
+    }
+}
+
+fn field_errors(errors: Vec<FieldError>) -> Result<(), Error> {
+    match errors.is_empty() {
+        true => Ok(()),
+        false => Err(Error::InvalidFields(errors)),
+    }
+}
+
+impl NewWork {
+    /// `Error::InvalidFields` listing every field out of bounds.
+    pub fn validate(&self) -> Result<(), Error> {
+        let mut errors: Vec<FieldError> = Vec::new();
+        check_add_up_to(self.add_up_to, &mut errors);
+        if let Some(work_code) = &self.work_code {
+            check_work_code(work_code, &mut errors);
+        }
+        if let Some(metadata) = &self.metadata {
+            check_metadata(metadata, &mut errors);
+        }
+        field_errors(errors)
+    }
+}
+
+/// The JSON body of `PUT /work/{id}`: every field a client can change,
+/// a missing `metadata` clears it.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct WorkReplace {
+    pub add_up_to: i32,
+    #[serde(default)]
+    pub metadata: Option<serde_json::Value>,
+}
+
+impl WorkReplace {
+    /// `Error::InvalidFields` listing every field out of bounds.
+    pub fn validate(&self) -> Result<(), Error> {
+        let mut errors: Vec<FieldError> = Vec::new();
+        check_add_up_to(self.add_up_to, &mut errors);
+        if let Some(metadata) = &self.metadata {
+            check_metadata(metadata, &mut errors);
+        }
+        field_errors(errors)
+    }
+}
+
+/// The JSON body of `PATCH /work/{id}`: only the fields present change,
+/// e.g. `{"status": "cancelled"}` or `{"metadata": null}` to clear the metadata.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct WorkPatch {
+    #[serde(default)]
+    pub add_up_to: Option<i32>,
+    // only `cancelled`, the `task_consumer` drives the other statuses and sets the result
+    #[serde(default)]
+    pub status: Option<WorkStatus>,
+    // `Some(None)` for an explicit `null`, which clears the metadata
+    #[serde(default, deserialize_with = "present")]
+    #[schemars(with = "Option<serde_json::Value>")]
+    pub metadata: Option<Option<serde_json::Value>>,
+}
+
+// tells a `null` field apart from a missing one, the latter falls back on `default`
+fn present<'de, T, D>(deserializer: D) -> Result<Option<Option<T>>, D::Error>
+where
+    T: Deserialize<'de>,
+    D: serde::Deserializer<'de>,
+{
+    Option::<T>::deserialize(deserializer).map(Some)
+}
+
+impl WorkPatch {
+    /// `Error::InvalidFields` listing every field out of bounds.
+    pub fn validate(&self) -> Result<(), Error> {
+        let mut errors: Vec<FieldError> = Vec::new();
+        if let Some(add_up_to) = self.add_up_to {
+            check_add_up_to(add_up_to, &mut errors);
+        }
+        match self.status {
+            Some(WorkStatus::Cancelled) | None => (),
+            Some(_) => errors.push(FieldError::new(
+                "status",
+                "must be 'cancelled', the other statuses are up to the task_consumer",
+            )),
+        }
+        if let Some(Some(metadata)) = &self.metadata {
+            check_metadata(metadata, &mut errors);
+        }
+        if self.add_up_to.is_none() && self.status.is_none() && self.metadata.is_none() {
+            errors.push(FieldError::new("*", "at least one field must be set"));
+        }
+        field_errors(errors)
+    }
+}
+
+/// The changes to apply to a work, see `WorkStore::update_work`.
+/// `None` leaves the field as it is.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct WorkUpdate {
+    // only before the work runs, i.e. `Pending` or `Queued`
+    pub add_up_to: Option<i32>,
+    // a valid transition from the current status
+    pub status: Option<WorkStatus>,
+    // `Some(None)` clears the metadata
+    pub metadata: Option<Option<serde_json::Value>>,
+}
+
+impl WorkUpdate {
+    /// Check the changes against the current state of the work, `Error::Conflict` when refused.
+    pub fn check(&self, current: &Work) -> Result<(), Error> {
+        let not_started = matches!(current.status, WorkStatus::Pending | WorkStatus::Queued);
+        if self.add_up_to.is_some() && !not_started {
+            return Err(Error::Conflict(format!(
+                "The add_up_to of a {} work cannot change",
+                current.status
+            )));
+        }
+        match self.status {
+            Some(status) => current.status.check_transition(status),
+            None => Ok(()),
+        }
+    }
+}
+
+impl From<WorkReplace> for WorkUpdate {
+    fn from(replace: WorkReplace) -> WorkUpdate {
+        WorkUpdate {
+            add_up_to: Some(replace.add_up_to),
+            status: None,
+            metadata: Some(replace.metadata),
+        }
+    }
+}
+
+impl From<WorkPatch> for WorkUpdate {
+    fn from(patch: WorkPatch) -> WorkUpdate {
+        WorkUpdate {
+            add_up_to: patch.add_up_to,
+            status: patch.status,
+            metadata: patch.metadata,
+        }
+    }
+}
+
+/// The lifecycle of a work:
+///
+/// ```text
+/// Pending ──> Queued ──> Running ──> Succeeded
+///    │          │           ├──────> Failed
+///    └──────────┴───────────┴──────> Cancelled
+/// ```
+///
+/// `Pending` can also go straight to `Running`, the last three are final.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq, Hash, Serialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum WorkStatus {
+    Pending,
+    Queued,
+    Running,
+    Succeeded,
+    Failed,
+    Cancelled,
+}
+
+impl WorkStatus {
+    pub const ALL: &'static [WorkStatus] = &[
+        WorkStatus::Pending,
+        WorkStatus::Queued,
+        WorkStatus::Running,
+        WorkStatus::Succeeded,
+        WorkStatus::Failed,
+        WorkStatus::Cancelled,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            WorkStatus::Pending => "pending",
+            WorkStatus::Queued => "queued",
+            WorkStatus::Running => "running",
+            WorkStatus::Succeeded => "succeeded",
+            WorkStatus::Failed => "failed",
+            WorkStatus::Cancelled => "cancelled",
+        }
+    }
+
+    pub fn is_final(&self) -> bool {
+        matches!(
+            self,
+            WorkStatus::Succeeded | WorkStatus::Failed | WorkStatus::Cancelled
+        )
+    }
+
+    pub fn can_transition_to(&self, next: WorkStatus) -> bool {
+        matches!(
+            (self, next),
+            (WorkStatus::Pending, WorkStatus::Queued)
+                | (WorkStatus::Pending, WorkStatus::Running)
+                | (WorkStatus::Queued, WorkStatus::Running)
+                | (WorkStatus::Running, WorkStatus::Succeeded)
+                | (WorkStatus::Running, WorkStatus::Failed)
+        ) || (next == WorkStatus::Cancelled && !self.is_final())
+    }
+
+    /// The statuses a work can reach `next` from.
+    pub fn sources_of(next: WorkStatus) -> Vec<WorkStatus> {
+        WorkStatus::ALL
+            .iter()
+            .filter(|s| s.can_transition_to(next))
+            .copied()
+            .collect()
+    }
+
+    // `Error::Conflict` when the transition is not allowed
+    pub fn check_transition(&self, next: WorkStatus) -> Result<(), Error> {
+        match self.can_transition_to(next) {
+            true => Ok(()),
+            false => Err(Error::Conflict(format!(
+                "A work cannot go from {} to {}",
+                self, next
+            ))),
+        }
+    }
 }
 
-#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq, Serialize)]
+impl fmt::Display for WorkStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl FromStr for WorkStatus {
+    type Err = Error;
+
+    fn from_str(name: &str) -> Result<WorkStatus, Error> {
+        WorkStatus::ALL
+            .iter()
+            .find(|s| s.as_str() == name)
+            .copied()
+            .ok_or_else(|| Error::Validation(format!("Unknown work status '{}'", name)))
+    }
+}
+
+/// The outcome of the computation of a work, see `WorkStore::complete_work`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct WorkResult {
+    pub value: i64,
+    pub started_at: DateTime<Utc>,
+    pub finished_at: DateTime<Utc>,
+}
+
+impl WorkResult {
+    pub fn duration_ms(&self) -> i64 {
+        (self.finished_at - self.started_at).num_milliseconds()
+    }
+}
+
+/// A work to do sent over AMQP, by `task_producer` or `POST /work/demands`.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize, JsonSchema)]
 pub struct WorkDemand {
     pub add_up_to: i32,
+    #[serde(default)]
     pub done: bool,
+    // the `work_code` its work gets once consumed, for the HTTP clients to poll it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tracking_id: Option<String>,
+}
+
+impl WorkDemand {
+    /// `Error::InvalidFields` listing every field a client cannot send.
+    pub fn validate(&self) -> Result<(), Error> {
+        let mut errors: Vec<FieldError> = Vec::new();
+        check_add_up_to(self.add_up_to, &mut errors);
+        if self.done {
+            errors.push(FieldError::new(
+                "done",
+                "must be false, nothing is done yet",
+            ));
+        }
+        if self.tracking_id.is_some() {
+            errors.push(FieldError::new("tracking_id", "is assigned by the API"));
+        }
+        field_errors(errors)
+    }
 }
 
-#[derive(Debug, Deserialize, PartialEq, Eq, Serialize)]
-pub struct Error {
-    pub http_code: u16,
-    pub message: String,
+/// The body of `POST /work/demands` (and of `GET /work/demands/{tracking_id}` while queued).
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize, JsonSchema)]
+pub struct WorkDemandReceipt {
+    pub tracking_id: String,
 }
 
-#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize, JsonSchema)]
 pub struct Event {
     pub id: i32,
     pub work_code: String,
-    pub variable: String,
-    pub value: String,
+    pub variable: EventVariable,
+    // `None` for the events that only mark a point in time (e.g. `ComputeStart`)
+    pub value: Option<EventValue>,
     #[serde(with = "ts_seconds_option")]
+    #[schemars(with = "Option<i64>", description = "Seconds since epoch")]
     pub created_on: Option<DateTime<Utc>>,
 }
 
-pub const VAR_COMPUTE_START: &'static str = "compute/start";
-pub const VAR_COMPUTE_STOP: &'static str = "compute/stop";
-pub const VAR_COMPUTE_RESULT: &'static str = "compute/result";
+/// The events of a work in chronological order, e.g. the JSON body of `GET /work/{id}/events`.
+#[derive(Clone, Debug, PartialEq, Serialize, JsonSchema)]
+pub struct WorkTimeline {
+    pub work_id: i32,
+    pub work_code: String,
+    pub status: WorkStatus,
+    pub events: Vec<TimelineEvent>,
+    // from the first `compute/start` to the following `compute/stop`, `None` until both are there
+    pub compute_duration_ms: Option<i64>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, JsonSchema)]
+pub struct TimelineEvent {
+    #[serde(flatten)]
+    pub event: Event,
+    // since the first event of the timeline
+    pub elapsed_ms: Option<i64>,
+}
+
+impl WorkTimeline {
+    /// Sort the `events` of `work` (oldest first, ties broken by `id`) and derive the durations.
+    pub fn new(work: &Work, events: Vec<Event>) -> WorkTimeline {
+        let mut events = events;
+        events.sort_by(|a, b| a.created_on.cmp(&b.created_on).then(a.id.cmp(&b.id)));
+
+        let first_on = events.iter().find_map(|e| e.created_on);
+        let started_on = events
+            .iter()
+            .find(|e| e.variable == EventVariable::ComputeStart)
+            .and_then(|e| e.created_on);
+        let stopped_on = started_on.and_then(|started_on| {
+            events
+                .iter()
+                .filter(|e| e.variable == EventVariable::ComputeStop)
+                .find_map(|e| e.created_on.filter(|&on| on >= started_on))
+        });
+        let compute_duration_ms = match (started_on, stopped_on) {
+            (Some(started_on), Some(stopped_on)) => {
+                Some((stopped_on - started_on).num_milliseconds())
+            }
+            _ => None,
+        };
+
+        let events = events
+            .into_iter()
+            .map(|event| TimelineEvent {
+                elapsed_ms: match (first_on, event.created_on) {
+                    (Some(first_on), Some(on)) => Some((on - first_on).num_milliseconds()),
+                    _ => None,
+                },
+                event,
+            })
+            .collect();
+        WorkTimeline {
+            work_id: work.id,
+            work_code: work.work_code.clone(),
+            status: work.status,
+            events,
+            compute_duration_ms,
+        }
+    }
+}
+
+/// The registry of the event variables, stored as their `as_str` name.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq, Hash, Serialize, JsonSchema)]
+pub enum EventVariable {
+    #[serde(rename = "compute/start")]
+    ComputeStart,
+    #[serde(rename = "compute/stop")]
+    ComputeStop,
+    // the value is the `EventValue::Integer` outcome
+    #[serde(rename = "compute/result")]
+    ComputeResult,
+    // the persistence of a computation failed, the value is the `EventValue::Text` error
+    #[serde(rename = "compute/failure")]
+    ComputeFailure,
+}
+
+impl EventVariable {
+    pub const ALL: &'static [EventVariable] = &[
+        EventVariable::ComputeStart,
+        EventVariable::ComputeStop,
+        EventVariable::ComputeResult,
+        EventVariable::ComputeFailure,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EventVariable::ComputeStart => "compute/start",
+            EventVariable::ComputeStop => "compute/stop",
+            EventVariable::ComputeResult => "compute/result",
+            EventVariable::ComputeFailure => "compute/failure",
+        }
+    }
+}
+
+impl fmt::Display for EventVariable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl FromStr for EventVariable {
+    type Err = Error;
+
+    fn from_str(name: &str) -> Result<EventVariable, Error> {
+        EventVariable::ALL
+            .iter()
+            .find(|v| v.as_str() == name)
+            .copied()
+            .ok_or_else(|| Error::Validation(format!("Unknown event variable '{}'", name)))
+    }
+}
+
+/// The payload of an event, stored as JSON e.g. `{"type": "integer", "value": 10}`.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize, JsonSchema)]
+#[serde(tag = "type", content = "value", rename_all = "snake_case")]
+pub enum EventValue {
+    Integer(i64),
+    Float(f64),
+    Bool(bool),
+    Text(String),
+    Json(serde_json::Value),
+}
+
+impl EventValue {
+    pub fn as_i64(&self) -> Option<i64> {
+        match self {
+            EventValue::Integer(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    // integers are widened, handy to aggregate numeric values
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            EventValue::Integer(value) => Some(*value as f64),
+            EventValue::Float(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            EventValue::Bool(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            EventValue::Text(value) => Some(value.as_str()),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,517 @@
+use std::cmp::Ordering;
+use std::convert::TryFrom;
+use std::str::FromStr;
+
+use chrono::{DateTime, TimeZone, Utc};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::error::Error;
+use crate::model::{Event, EventVariable, Work, WorkStatus};
+
+/// How to match the `work_code` column.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum CodeMatch {
+    Prefix(String),
+    Contains(String),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WorkSortField {
+    Id,
+    AddUpTo,
+    CreatedOn,
+    UpdatedOn,
+}
+
+impl WorkSortField {
+    pub const ALL: &'static [WorkSortField] = &[
+        WorkSortField::Id,
+        WorkSortField::AddUpTo,
+        WorkSortField::CreatedOn,
+        WorkSortField::UpdatedOn,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            WorkSortField::Id => "id",
+            WorkSortField::AddUpTo => "add_up_to",
+            WorkSortField::CreatedOn => "created_on",
+            WorkSortField::UpdatedOn => "updated_on",
+        }
+    }
+
+    /// The value of the field for `work`, `None` for a time not set yet.
+    pub fn key_of(&self, work: &Work) -> Option<SortKey> {
+        match self {
+            WorkSortField::Id => Some(SortKey::Int(work.id)),
+            WorkSortField::AddUpTo => Some(SortKey::Int(work.add_up_to)),
+            WorkSortField::CreatedOn => work.created_on.map(SortKey::Time),
+            WorkSortField::UpdatedOn => work.updated_on.map(SortKey::Time),
+        }
+    }
+}
+
+/// The value of a `WorkSortField`, see `WorkCursor`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum SortKey {
+    Int(i32),
+    Time(DateTime<Utc>),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SortDirection {
+    Asc,
+    Desc,
+}
+
+/// Ordering of the search results, ties are always broken by `id`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct WorkSort {
+    pub field: WorkSortField,
+    pub direction: SortDirection,
+}
+
+impl Default for WorkSort {
+    fn default() -> Self {
+        WorkSort {
+            field: WorkSortField::CreatedOn,
+            direction: SortDirection::Asc,
+        }
+    }
+}
+
+/// The field name, descending when prefixed by `-` e.g. `-created_on`.
+impl FromStr for WorkSort {
+    type Err = Error;
+
+    fn from_str(sort: &str) -> Result<WorkSort, Error> {
+        let (name, direction) = match sort.strip_prefix('-') {
+            Some(name) => (name, SortDirection::Desc),
+            None => (sort, SortDirection::Asc),
+        };
+        WorkSortField::ALL
+            .iter()
+            .find(|f| f.as_str() == name)
+            .map(|&field| WorkSort { field, direction })
+            .ok_or_else(|| Error::Validation(format!("Unknown sort field '{}'", name)))
+    }
+}
+
+impl WorkSort {
+    pub fn compare(&self, a: &Work, b: &Work) -> Ordering {
+        let ord = self
+            .field
+            .key_of(a)
+            .cmp(&self.field.key_of(b))
+            .then(a.id.cmp(&b.id));
+        match self.direction {
+            SortDirection::Asc => ord,
+            SortDirection::Desc => ord.reverse(),
+        }
+    }
+}
+
+/// Where a page of works ended, in the `(field, id)` order of the sort.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct WorkCursor {
+    pub field: WorkSortField,
+    pub key: SortKey,
+    pub id: i32,
+}
+
+impl WorkCursor {
+    pub fn from_work(work: &Work, field: WorkSortField) -> Option<WorkCursor> {
+        field.key_of(work).map(|key| WorkCursor {
+            field,
+            key,
+            id: work.id,
+        })
+    }
+
+    /// The opaque string handed over to the clients.
+    pub fn encode(&self) -> String {
+        // the times in microseconds, the precision of the DB
+        let key = match self.key {
+            SortKey::Int(value) => i64::from(value),
+            SortKey::Time(time) => {
+                time.timestamp() * 1_000_000 + i64::from(time.timestamp_subsec_micros())
+            }
+        };
+        let raw = format!("{}:{}:{}", self.field.as_str(), key, self.id);
+        base64::encode_config(raw, base64::URL_SAFE_NO_PAD)
+    }
+
+    pub fn decode(cursor: &str) -> Result<WorkCursor, Error> {
+        let invalid = || Error::Validation(format!("Invalid cursor '{}'", cursor));
+        let raw = base64::decode_config(cursor, base64::URL_SAFE_NO_PAD).map_err(|_| invalid())?;
+        let raw = String::from_utf8(raw).map_err(|_| invalid())?;
+        let parts: Vec<&str> = raw.split(':').collect();
+        if parts.len() != 3 {
+            return Err(invalid());
+        }
+        let field = *WorkSortField::ALL
+            .iter()
+            .find(|f| f.as_str() == parts[0])
+            .ok_or_else(invalid)?;
+        let key: i64 = parts[1].parse().map_err(|_| invalid())?;
+        let id: i32 = parts[2].parse().map_err(|_| invalid())?;
+        let key = match field {
+            WorkSortField::Id | WorkSortField::AddUpTo => {
+                SortKey::Int(i32::try_from(key).map_err(|_| invalid())?)
+            }
+            WorkSortField::CreatedOn | WorkSortField::UpdatedOn => {
+                let micros = key.rem_euclid(1_000_000) as u32;
+                Utc.timestamp_opt(key.div_euclid(1_000_000), micros * 1000)
+                    .single()
+                    .map(SortKey::Time)
+                    .ok_or_else(invalid)?
+            }
+        };
+        Ok(WorkCursor { field, key, id })
+    }
+
+    // strictly past the cursor, in the `direction` of the `(field, id)` order
+    fn is_passed_by(&self, work: &Work, direction: SortDirection) -> bool {
+        let ord = self
+            .field
+            .key_of(work)
+            .cmp(&Some(self.key))
+            .then(work.id.cmp(&self.id));
+        match direction {
+            SortDirection::Asc => ord == Ordering::Greater,
+            SortDirection::Desc => ord == Ordering::Less,
+        }
+    }
+}
+
+/// A page of works, e.g. the JSON body of `GET /work/search`:
+/// `next_cursor` asks for the following page, `None` on the last one.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize, JsonSchema)]
+pub struct WorkPage {
+    pub items: Vec<Work>,
+    pub next_cursor: Option<String>,
+}
+
+pub const DEFAULT_PAGE_SIZE: u32 = 50;
+pub const MAX_PAGE_SIZE: u32 = 500;
+
+/// Filters, ordering and paging for searching works.
+///
+/// Every filter is optional, an empty query matches every work.
+/// The time windows are exclusive: `created_after` means strictly after.
+///
+/// ```
+/// use pp_lib::model::WorkStatus;
+/// use pp_lib::query::WorkQuery;
+///
+/// let query = WorkQuery::new()
+///     .work_code_prefix("api-")
+///     .status(WorkStatus::Pending)
+///     .limit(10);
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct WorkQuery {
+    pub work_code: Option<CodeMatch>,
+    pub status: Option<WorkStatus>,
+    // `true` for the works in a final status, `false` for the others
+    pub done: Option<bool>,
+    pub min_add_up_to: Option<i32>,
+    pub max_add_up_to: Option<i32>,
+    pub created_after: Option<DateTime<Utc>>,
+    pub created_before: Option<DateTime<Utc>>,
+    pub updated_after: Option<DateTime<Utc>>,
+    pub updated_before: Option<DateTime<Utc>>,
+    pub sort: WorkSort,
+    // keyset paging, the cursor must come from a page with the same `sort.field`
+    pub after: Option<WorkCursor>,
+    pub limit: Option<u32>,
+    pub offset: Option<u32>,
+}
+
+impl WorkQuery {
+    pub fn new() -> WorkQuery {
+        WorkQuery::default()
+    }
+
+    pub fn work_code_prefix(mut self, prefix: &str) -> WorkQuery {
+        self.work_code = Some(CodeMatch::Prefix(String::from(prefix)));
+        self
+    }
+
+    pub fn work_code_contains(mut self, fragment: &str) -> WorkQuery {
+        self.work_code = Some(CodeMatch::Contains(String::from(fragment)));
+        self
+    }
+
+    pub fn status(mut self, status: WorkStatus) -> WorkQuery {
+        self.status = Some(status);
+        self
+    }
+
+    pub fn done(mut self, done: bool) -> WorkQuery {
+        self.done = Some(done);
+        self
+    }
+
+    pub fn add_up_to_between(mut self, min: Option<i32>, max: Option<i32>) -> WorkQuery {
+        self.min_add_up_to = min;
+        self.max_add_up_to = max;
+        self
+    }
+
+    pub fn created_between(
+        mut self,
+        after: Option<DateTime<Utc>>,
+        before: Option<DateTime<Utc>>,
+    ) -> WorkQuery {
+        self.created_after = after;
+        self.created_before = before;
+        self
+    }
+
+    pub fn updated_between(
+        mut self,
+        after: Option<DateTime<Utc>>,
+        before: Option<DateTime<Utc>>,
+    ) -> WorkQuery {
+        self.updated_after = after;
+        self.updated_before = before;
+        self
+    }
+
+    pub fn sort_by(mut self, field: WorkSortField, direction: SortDirection) -> WorkQuery {
+        self.sort = WorkSort { field, direction };
+        self
+    }
+
+    /// The works past `cursor` (see `WorkStore::search_work_page`).
+    pub fn after(mut self, cursor: WorkCursor) -> WorkQuery {
+        self.after = Some(cursor);
+        self
+    }
+
+    pub fn limit(mut self, limit: u32) -> WorkQuery {
+        self.limit = Some(limit);
+        self
+    }
+
+    pub fn offset(mut self, offset: u32) -> WorkQuery {
+        self.offset = Some(offset);
+        self
+    }
+
+    /// `Error::Validation` when the cursor was made for another sort field.
+    pub fn check_cursor(&self) -> Result<(), Error> {
+        match self.after {
+            Some(cursor) if cursor.field != self.sort.field => Err(Error::Validation(format!(
+                "The cursor pages through the works sorted on {}, not on {}",
+                cursor.field.as_str(),
+                self.sort.field.as_str()
+            ))),
+            _ => Ok(()),
+        }
+    }
+
+    /// The filters evaluated in Rust, for the stores not backed by SQL.
+    pub fn matches(&self, work: &Work) -> bool {
+        let code_ok = match &self.work_code {
+            Some(CodeMatch::Prefix(prefix)) => work.work_code.starts_with(prefix.as_str()),
+            Some(CodeMatch::Contains(fragment)) => work.work_code.contains(fragment.as_str()),
+            None => true,
+        };
+        code_ok
+            && self.status.iter().all(|&status| work.status == status)
+            && self.done.iter().all(|&done| work.status.is_final() == done)
+            && self.min_add_up_to.iter().all(|&min| work.add_up_to >= min)
+            && self.max_add_up_to.iter().all(|&max| work.add_up_to <= max)
+            && after(work.created_on, self.created_after)
+            && before(work.created_on, self.created_before)
+            && after(work.updated_on, self.updated_after)
+            && before(work.updated_on, self.updated_before)
+            && self
+                .after
+                .iter()
+                .all(|cursor| cursor.is_passed_by(work, self.sort.direction))
+    }
+}
+
+/// The parameters of `WorkQuery::from_query_string`.
+pub const WORK_QUERY_PARAMS: &[&str] = &[
+    "work_code",
+    "done",
+    "status",
+    "min_add_up_to",
+    "max_add_up_to",
+    "created_after",
+    "created_before",
+    "sort",
+    "limit",
+    "cursor",
+];
+
+impl WorkQuery {
+    /// Parse a percent-encoded query string e.g. `work_code=api-&status=pending&sort=-created_on`.
+    ///
+    /// The times are seconds since epoch or RFC 3339 (`+` must be encoded as `%2B`).
+    /// The `sort` is a `WorkSortField`, descending when prefixed by `-` e.g. `-add_up_to`,
+    /// the `limit` defaults to `DEFAULT_PAGE_SIZE` and is capped to `MAX_PAGE_SIZE`,
+    /// the `cursor` is the `next_cursor` of the previous page, sorted the same way.
+    /// Unknown or repeated parameters and invalid values are a `Error::Validation`.
+    ///
+    /// ```
+    /// use pp_lib::query::WorkQuery;
+    ///
+    /// let query = WorkQuery::from_query_string("work_code=api-&done=false&limit=10").unwrap();
+    /// assert_eq!(Some(false), query.done);
+    /// assert!(WorkQuery::from_query_string("colour=red").is_err());
+    /// ```
+    pub fn from_query_string(query_string: &str) -> Result<WorkQuery, Error> {
+        let mut query = WorkQuery::new();
+        let mut seen: Vec<String> = Vec::new();
+        for (name, value) in form_urlencoded::parse(query_string.as_bytes()) {
+            let name = name.into_owned();
+            if seen.contains(&name) {
+                return Err(Error::Validation(format!(
+                    "The query parameter '{}' is repeated",
+                    name
+                )));
+            }
+            let value = value.as_ref();
+            match name.as_str() {
+                "work_code" => query = query.work_code_prefix(value),
+                "done" => query.done = Some(parse_param(&name, value)?),
+                "status" => query.status = Some(value.parse()?),
+                "min_add_up_to" => query.min_add_up_to = Some(parse_param(&name, value)?),
+                "max_add_up_to" => query.max_add_up_to = Some(parse_param(&name, value)?),
+                "created_after" => query.created_after = Some(parse_time(&name, value)?),
+                "created_before" => query.created_before = Some(parse_time(&name, value)?),
+                "sort" => query.sort = value.parse()?,
+                "limit" => query.limit = Some(parse_param(&name, value)?),
+                "cursor" => query.after = Some(WorkCursor::decode(value)?),
+                _ => {
+                    return Err(Error::Validation(format!(
+                        "Unknown query parameter '{}', expected one of: {}",
+                        name,
+                        WORK_QUERY_PARAMS.join(", ")
+                    )))
+                }
+            }
+            seen.push(name);
+        }
+
+        query.check_cursor()?;
+        query.limit = match query.limit {
+            None => Some(DEFAULT_PAGE_SIZE),
+            Some(0) => {
+                return Err(Error::Validation(String::from(
+                    "The limit must be positive",
+                )))
+            }
+            Some(limit) => Some(limit.min(MAX_PAGE_SIZE)),
+        };
+        Ok(query)
+    }
+}
+
+fn parse_param<T: FromStr>(name: &str, value: &str) -> Result<T, Error> {
+    value.parse::<T>().map_err(|_| {
+        Error::Validation(format!(
+            "Invalid value '{}' for the query parameter '{}'",
+            value, name
+        ))
+    })
+}
+
+fn parse_time(name: &str, value: &str) -> Result<DateTime<Utc>, Error> {
+    let time = match value.parse::<i64>() {
+        Ok(seconds) => Utc.timestamp_opt(seconds, 0).single(),
+        Err(_) => DateTime::parse_from_rfc3339(value)
+            .ok()
+            .map(|time| time.with_timezone(&Utc)),
+    };
+    time.ok_or_else(|| {
+        Error::Validation(format!(
+            "Invalid time '{}' for the query parameter '{}', expected seconds since epoch or RFC 3339",
+            value, name
+        ))
+    })
+}
+
+fn after(value: Option<DateTime<Utc>>, bound: Option<DateTime<Utc>>) -> bool {
+    match bound {
+        Some(bound) => matches!(value, Some(v) if v > bound),
+        None => true,
+    }
+}
+
+fn before(value: Option<DateTime<Utc>>, bound: Option<DateTime<Utc>>) -> bool {
+    match bound {
+        Some(bound) => matches!(value, Some(v) if v < bound),
+        None => true,
+    }
+}
+
+/// Filters and paging for listing events, always sorted chronologically.
+///
+/// ```
+/// use pp_lib::model::EventVariable;
+/// use pp_lib::query::EventQuery;
+///
+/// let query = EventQuery::new()
+///     .work_code("api-bjq8euwsEA")
+///     .variable(EventVariable::ComputeResult);
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct EventQuery {
+    pub work_code: Option<String>,
+    pub variable: Option<EventVariable>,
+    pub created_after: Option<DateTime<Utc>>,
+    pub created_before: Option<DateTime<Utc>>,
+    pub limit: Option<u32>,
+    pub offset: Option<u32>,
+}
+
+impl EventQuery {
+    pub fn new() -> EventQuery {
+        EventQuery::default()
+    }
+
+    pub fn work_code(mut self, work_code: &str) -> EventQuery {
+        self.work_code = Some(String::from(work_code));
+        self
+    }
+
+    pub fn variable(mut self, variable: EventVariable) -> EventQuery {
+        self.variable = Some(variable);
+        self
+    }
+
+    pub fn created_between(
+        mut self,
+        after: Option<DateTime<Utc>>,
+        before: Option<DateTime<Utc>>,
+    ) -> EventQuery {
+        self.created_after = after;
+        self.created_before = before;
+        self
+    }
+
+    pub fn limit(mut self, limit: u32) -> EventQuery {
+        self.limit = Some(limit);
+        self
+    }
+
+    pub fn offset(mut self, offset: u32) -> EventQuery {
+        self.offset = Some(offset);
+        self
+    }
+
+    /// The filters evaluated in Rust, for the stores not backed by SQL.
+    pub fn matches(&self, event: &Event) -> bool {
+        self.work_code.iter().all(|wc| event.work_code == *wc)
+            && self.variable.iter().all(|v| event.variable == *v)
+            && after(event.created_on, self.created_after)
+            && before(event.created_on, self.created_before)
+    }
+}
@@ -0,0 +1,318 @@
+use std::collections::HashMap;
+use std::mem;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use chrono::{NaiveDate, SubsecRound, Utc};
+use log;
+
+use super::store::{quota_exceeded, WorkStore};
+use crate::auth::{ApiKey, Scope};
+use crate::error::Error;
+use crate::model::{Event, Work, WorkResult, WorkStatus, WorkUpdate};
+use crate::query::{EventQuery, WorkQuery};
+
+#[derive(Clone, Debug, Default)]
+struct State {
+    works: Vec<Work>,
+    // soft deleted, out of sight but still holding their `work_code` and events
+    deleted_works: Vec<Work>,
+    events: Vec<Event>,
+    // along with their `key_hash`
+    api_keys: Vec<(String, ApiKey)>,
+    // the works created by client and day
+    work_quotas: HashMap<(String, NaiveDate), u32>,
+    last_work_id: i32,
+    last_event_id: i32,
+    last_api_key_id: i32,
+}
+
+/// In-memory `WorkStore`, it mimics the Postgres tables `works`, `events`, `api_keys` and `work_quotas`.
+///
+/// Cloning a `MemoryStore` is cheap and the clones share the same data,
+/// so it can be handed over to multiple threads.
+#[derive(Clone, Debug, Default)]
+pub struct MemoryStore {
+    state: Arc<Mutex<State>>,
+}
+
+impl MemoryStore {
+    pub fn new() -> MemoryStore {
+        MemoryStore::default()
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State> {
+        // a panic while holding the lock leaves the data consistent
+        // (every operation is a single push or assignment), so keep going
+        match self.state.lock() {
+            Ok(guard) => guard,
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
+}
+
+fn all_works(state: &State) -> impl Iterator<Item = &Work> {
+    state.works.iter().chain(state.deleted_works.iter())
+}
+
+fn find_work_mut(state: &mut State, work_id: i32) -> Result<&mut Work, Error> {
+    match state.works.iter_mut().find(|w| w.id == work_id) {
+        Some(work) => Ok(work),
+        None => Err(Error::NotFound(format!(
+            "no work updated with id {}",
+            work_id
+        ))),
+    }
+}
+
+impl WorkStore for MemoryStore {
+    fn create_work(&mut self, work: Work) -> Result<Work, Error> {
+        let mut state = self.lock();
+        // mimic the UNIQUE constraint on `works.work_code`
+        if all_works(&state).any(|w| w.work_code == work.work_code) {
+            return Err(Error::Conflict(format!(
+                "A work with work_code {} already exists",
+                work.work_code
+            )));
+        }
+        state.last_work_id += 1;
+        let created = Work {
+            id: state.last_work_id,
+            ..work
+        };
+        log::info!(
+            "For work_code {} and work_add_up_to {}, the ID is: {:?}",
+            created.work_code,
+            created.add_up_to,
+            created.id
+        );
+        state.works.push(created.clone());
+        Ok(created)
+    }
+
+    fn retrieve_work(&mut self, id: i32) -> Result<Work, Error> {
+        let state = self.lock();
+        match state.works.iter().find(|w| w.id == id) {
+            Some(work) => Ok(work.clone()),
+            None => Err(Error::NotFound(format!("no work retrieved with id {}", id))),
+        }
+    }
+
+    fn search_work(&mut self, query: &WorkQuery) -> Result<Vec<Work>, Error> {
+        log::info!("Searching in memory for works with query {:?}", query);
+        query.check_cursor()?;
+        let state = self.lock();
+        let mut works: Vec<Work> = state
+            .works
+            .iter()
+            .filter(|w| query.matches(w))
+            .cloned()
+            .collect();
+        works.sort_by(|a, b| query.sort.compare(a, b));
+        let offset = query.offset.unwrap_or(0) as usize;
+        let limit = query.limit.map_or(usize::MAX, |l| l as usize);
+        Ok(works.into_iter().skip(offset).take(limit).collect())
+    }
+
+    fn update_work(&mut self, work_id: i32, update: &WorkUpdate) -> Result<Work, Error> {
+        let mut state = self.lock();
+        let work = find_work_mut(&mut state, work_id)?;
+        update.check(work)?;
+        if let Some(add_up_to) = update.add_up_to {
+            work.add_up_to = add_up_to;
+        }
+        if let Some(status) = update.status {
+            work.status = status;
+        }
+        if let Some(metadata) = &update.metadata {
+            work.metadata = metadata.clone();
+        }
+        work.updated_on = Some(Utc::now().round_subsecs(6));
+        Ok(work.clone())
+    }
+
+    fn soft_delete_work(&mut self, work_id: i32) -> Result<(), Error> {
+        let mut state = self.lock();
+        match state.works.iter().position(|w| w.id == work_id) {
+            Some(idx) => {
+                let work = state.works.remove(idx);
+                state.deleted_works.push(work);
+                Ok(())
+            }
+            None => Err(Error::NotFound(format!(
+                "no work deleted with id {}",
+                work_id
+            ))),
+        }
+    }
+
+    fn delete_work(&mut self, work_id: i32) -> Result<(), Error> {
+        let mut state = self.lock();
+        let work_code = match all_works(&state).find(|w| w.id == work_id) {
+            Some(work) => work.work_code.clone(),
+            None => {
+                return Err(Error::NotFound(format!(
+                    "no work deleted with id {}",
+                    work_id
+                )))
+            }
+        };
+        // mimic the foreign key `ON DELETE RESTRICT` on `events.work_code`
+        if state.events.iter().any(|e| e.work_code == work_code) {
+            return Err(Error::Conflict(format!(
+                "The work with id {} still has events",
+                work_id
+            )));
+        }
+        state.works.retain(|w| w.id != work_id);
+        state.deleted_works.retain(|w| w.id != work_id);
+        Ok(())
+    }
+
+    fn update_work_status(&mut self, work_id: i32, status: WorkStatus) -> Result<(), Error> {
+        let mut state = self.lock();
+        let work = find_work_mut(&mut state, work_id)?;
+        work.status.check_transition(status)?;
+        work.status = status;
+        work.updated_on = Some(Utc::now().round_subsecs(6));
+        Ok(())
+    }
+
+    fn complete_work(&mut self, work_id: i32, result: &WorkResult) -> Result<(), Error> {
+        let mut state = self.lock();
+        let work = find_work_mut(&mut state, work_id)?;
+        work.status.check_transition(WorkStatus::Succeeded)?;
+        work.status = WorkStatus::Succeeded;
+        work.updated_on = Some(Utc::now().round_subsecs(6));
+        work.result = Some(result.value);
+        work.started_at = Some(result.started_at);
+        work.finished_at = Some(result.finished_at);
+        work.duration_ms = Some(result.duration_ms());
+        Ok(())
+    }
+
+    fn create_event(&mut self, event: Event) -> Result<(), Error> {
+        let mut state = self.lock();
+        // mimic the foreign key on `events.work_code`
+        if !all_works(&state).any(|w| w.work_code == event.work_code) {
+            return Err(Error::NotFound(format!(
+                "Cannot create event: no work with work_code {}",
+                event.work_code
+            )));
+        }
+        state.last_event_id += 1;
+        let created = Event {
+            id: state.last_event_id,
+            ..event
+        };
+        state.events.push(created);
+        Ok(())
+    }
+
+    fn list_events(&mut self, query: &EventQuery) -> Result<Vec<Event>, Error> {
+        let state = self.lock();
+        let mut events: Vec<Event> = state
+            .events
+            .iter()
+            .filter(|e| query.matches(e))
+            .cloned()
+            .collect();
+        events.sort_by(|a, b| a.created_on.cmp(&b.created_on).then(a.id.cmp(&b.id)));
+        let offset = query.offset.unwrap_or(0) as usize;
+        let limit = query.limit.map_or(usize::MAX, |l| l as usize);
+        Ok(events.into_iter().skip(offset).take(limit).collect())
+    }
+
+    fn create_api_key(
+        &mut self,
+        name: &str,
+        key_hash: &str,
+        scopes: &[Scope],
+    ) -> Result<ApiKey, Error> {
+        let mut state = self.lock();
+        // mimic the UNIQUE constraint on `api_keys.key_hash`
+        if state.api_keys.iter().any(|(hash, _)| hash == key_hash) {
+            return Err(Error::Conflict(String::from("This API key already exists")));
+        }
+        state.last_api_key_id += 1;
+        let created = ApiKey {
+            id: state.last_api_key_id,
+            name: String::from(name),
+            scopes: scopes.to_vec(),
+            created_on: Some(Utc::now().round_subsecs(6)),
+            revoked_on: None,
+        };
+        state
+            .api_keys
+            .push((String::from(key_hash), created.clone()));
+        Ok(created)
+    }
+
+    fn find_api_key(&mut self, key_hash: &str) -> Result<ApiKey, Error> {
+        let state = self.lock();
+        state
+            .api_keys
+            .iter()
+            .find(|(hash, key)| hash == key_hash && key.revoked_on.is_none())
+            .map(|(_, key)| key.clone())
+            .ok_or_else(|| Error::NotFound(String::from("no API key with this hash")))
+    }
+
+    fn list_api_keys(&mut self) -> Result<Vec<ApiKey>, Error> {
+        let state = self.lock();
+        Ok(state.api_keys.iter().map(|(_, key)| key.clone()).collect())
+    }
+
+    fn revoke_api_key(&mut self, id: i32) -> Result<(), Error> {
+        let mut state = self.lock();
+        match state
+            .api_keys
+            .iter_mut()
+            .find(|(_, key)| key.id == id && key.revoked_on.is_none())
+        {
+            Some((_, key)) => {
+                key.revoked_on = Some(Utc::now().round_subsecs(6));
+                Ok(())
+            }
+            None => Err(Error::NotFound(format!(
+                "no API key revoked with id {}",
+                id
+            ))),
+        }
+    }
+
+    fn take_work_quota(&mut self, client: &str, day: NaiveDate, limit: u32) -> Result<u32, Error> {
+        let mut state = self.lock();
+        let created = state
+            .work_quotas
+            .entry((String::from(client), day))
+            .or_insert(0);
+        if *created >= limit {
+            return Err(quota_exceeded(client, limit));
+        }
+        *created += 1;
+        Ok(*created)
+    }
+
+    fn refund_work_quota(&mut self, client: &str, day: NaiveDate) -> Result<(), Error> {
+        let mut state = self.lock();
+        if let Some(created) = state.work_quotas.get_mut(&(String::from(client), day)) {
+            *created = created.saturating_sub(1);
+        }
+        Ok(())
+    }
+
+    fn in_transaction(
+        &mut self,
+        unit: &mut dyn FnMut(&mut dyn WorkStore) -> Result<(), Error>,
+    ) -> Result<(), Error> {
+        // hold the lock for the whole unit of work (the clones wait for it)
+        // and let it work on a private copy, swapped in only on success
+        let mut state = self.lock();
+        let mut tx = MemoryStore {
+            state: Arc::new(Mutex::new(state.clone())),
+        };
+        unit(&mut tx)?;
+        *state = mem::take(&mut *tx.lock());
+        Ok(())
+    }
+}
@@ -1,2 +1,5 @@
 pub mod db;
+pub mod memory;
+pub mod pool;
 pub mod queue;
+pub mod store;
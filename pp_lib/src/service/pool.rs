@@ -0,0 +1,82 @@
+use std::time::Duration;
+
+use postgres::NoTls;
+use r2d2::Pool;
+use r2d2_postgres::PostgresConnectionManager;
+
+use crate::error::Error;
+use crate::service::db;
+use crate::service::memory::MemoryStore;
+use crate::service::store::WorkStore;
+
+// how long to wait for a DB connection before giving up
+pub const CONNECTION_TIMEOUT: Duration = Duration::from_secs(5);
+// the health checks answer quickly, even when the pool is exhausted
+pub const PING_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Hands out work stores to concurrent workers (e.g. the HTTP threads of `pp_backend_api`),
+/// cloning it is cheap and the clones share the same connections or memory.
+#[derive(Clone)]
+pub enum StorePool {
+    Postgres(Pool<PostgresConnectionManager<NoTls>>),
+    Memory(MemoryStore),
+}
+
+impl StorePool {
+    /// A pool of at most `size` connections to the DB at `conn_str`.
+    pub fn postgres(conn_str: &str, size: u32) -> Result<StorePool, Error> {
+        let config = conn_str
+            .parse()
+            .map_err(|err| Error::database("Not able to parse the DB connection string", err))?;
+        let manager = PostgresConnectionManager::new(config, NoTls);
+        Pool::builder()
+            .max_size(size)
+            .connection_timeout(CONNECTION_TIMEOUT)
+            .build(manager)
+            .map(StorePool::Postgres)
+            .map_err(|err| pool_error("Not able to open the DB connection pool", err))
+    }
+
+    /// Run `unit` on a store: a pooled DB connection, given back once `unit` returns,
+    /// or the shared in-memory store.
+    pub fn with_store<T>(&self, unit: impl FnOnce(&mut dyn WorkStore) -> T) -> Result<T, Error> {
+        match self {
+            StorePool::Postgres(pool) => {
+                let mut client = pool
+                    .get()
+                    .map_err(|err| pool_error("No DB connection available", err))?;
+                Ok(unit(&mut *client))
+            }
+            StorePool::Memory(store) => Ok(unit(&mut store.clone())),
+        }
+    }
+
+    /// The name of the storage backend, e.g. for the health checks.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            StorePool::Postgres(_) => "postgres",
+            StorePool::Memory(_) => "memory",
+        }
+    }
+
+    /// Check the storage is reachable (a `SELECT 1` for the DB).
+    pub fn ping(&self) -> Result<(), Error> {
+        match self {
+            StorePool::Postgres(pool) => {
+                let mut client = pool
+                    .get_timeout(PING_TIMEOUT)
+                    .map_err(|err| pool_error("No DB connection available", err))?;
+                db::ping(&mut *client)
+            }
+            StorePool::Memory(_) => Ok(()),
+        }
+    }
+}
+
+// `r2d2` only keeps the message of the underlying `postgres::Error`
+fn pool_error(message: &str, err: r2d2::Error) -> Error {
+    Error::Database {
+        message: format!("{}: {}", message, err),
+        source: None,
+    }
+}
@@ -0,0 +1,153 @@
+use chrono::NaiveDate;
+use log;
+
+use crate::auth::{ApiKey, Scope};
+use crate::error::Error;
+use crate::factory;
+use crate::model::{Event, Work, WorkResult, WorkStatus, WorkTimeline, WorkUpdate};
+use crate::query::{EventQuery, WorkCursor, WorkPage, WorkQuery, DEFAULT_PAGE_SIZE};
+
+// how many random `work_code` suffixes to try before giving up
+pub const WORK_CODE_ATTEMPTS: u32 = 5;
+
+/// The error of `WorkStore::take_work_quota` once `client` reached its `limit`.
+pub fn quota_exceeded(client: &str, limit: u32) -> Error {
+    Error::TooManyRequests(format!(
+        "The client {} already created {} works today",
+        client, limit
+    ))
+}
+
+/// The persistence operations on works and events (and the API keys).
+///
+/// `service::db` implements it for a Postgres `Client`,
+/// `service::memory` keeps everything in the process memory
+/// (handy for unit tests and local runs without a database).
+pub trait WorkStore {
+    // `Error::Conflict` when the `work_code` is taken
+    fn create_work(&mut self, work: Work) -> Result<Work, Error>;
+
+    /// Create a work whose `work_code` ends with a random suffix
+    /// (see `factory::generate_random_work`), drawing a new suffix
+    /// whenever the code collides with an existing work.
+    fn create_work_retrying(&mut self, work: Work) -> Result<Work, Error> {
+        let mut work = work;
+        let mut attempt = 1;
+        loop {
+            match self.create_work(work.clone()) {
+                Err(Error::Conflict(message)) if attempt < WORK_CODE_ATTEMPTS => {
+                    log::warn!("Attempt {}: {}, retrying", attempt, message);
+                    work.work_code = factory::reroll_work_code(work.work_code.as_str());
+                    attempt += 1;
+                }
+                res => return res,
+            }
+        }
+    }
+
+    fn retrieve_work(&mut self, id: i32) -> Result<Work, Error>;
+
+    fn search_work(&mut self, query: &WorkQuery) -> Result<Vec<Work>, Error>;
+
+    /// The work with exactly this `work_code`, `Error::NotFound` otherwise.
+    fn find_work_by_code(&mut self, work_code: &str) -> Result<Work, Error> {
+        let query = WorkQuery::new().work_code_prefix(work_code);
+        self.search_work(&query)?
+            .into_iter()
+            .find(|work| work.work_code == work_code)
+            .ok_or_else(|| Error::NotFound(format!("No work with code {}", work_code)))
+    }
+
+    /// One page of `query.limit` works (`DEFAULT_PAGE_SIZE` when missing),
+    /// ask for the next one with `query.after(cursor)` where `cursor` is the decoded `next_cursor`,
+    /// keeping the same `query.sort` (`Error::Validation` otherwise).
+    fn search_work_page(&mut self, query: &WorkQuery) -> Result<WorkPage, Error> {
+        let limit = query.limit.unwrap_or(DEFAULT_PAGE_SIZE);
+        // one more work tells whether there is a next page
+        let mut probe = query.clone();
+        probe.limit = Some(limit.saturating_add(1));
+        let mut items = self.search_work(&probe)?;
+        let next_cursor = match items.len() > limit as usize {
+            true => {
+                items.truncate(limit as usize);
+                items
+                    .last()
+                    .and_then(|work| WorkCursor::from_work(work, query.sort.field))
+                    .map(|c| c.encode())
+            }
+            false => None,
+        };
+        Ok(WorkPage { items, next_cursor })
+    }
+
+    /// Apply the changes and return the updated work.
+    /// `Error::Conflict` when `add_up_to` changes after the work started running
+    /// or when the status transition is not allowed (see `WorkStatus`).
+    fn update_work(&mut self, work_id: i32, update: &WorkUpdate) -> Result<Work, Error>;
+
+    // hide the work from now on, the row and its events stay for the records
+    fn soft_delete_work(&mut self, work_id: i32) -> Result<(), Error>;
+
+    // remove the row (even a soft deleted one),
+    // `Error::NotFound` when missing, `Error::Conflict` when the work still has events
+    fn delete_work(&mut self, work_id: i32) -> Result<(), Error>;
+
+    /// Move the work to `status` (`updated_on` field as well...),
+    /// `Error::Conflict` when its current status does not allow it (see `WorkStatus`).
+    fn update_work_status(&mut self, work_id: i32, status: WorkStatus) -> Result<(), Error>;
+
+    // from `Running` to `Succeeded`, also storing the outcome of the computation
+    fn complete_work(&mut self, work_id: i32, result: &WorkResult) -> Result<(), Error>;
+
+    // `Error::NotFound` when the `work_code` does not belong to a work
+    fn create_event(&mut self, event: Event) -> Result<(), Error>;
+
+    // oldest first
+    fn list_events(&mut self, query: &EventQuery) -> Result<Vec<Event>, Error>;
+
+    /// The events of a (not deleted) work, oldest first, with the derived durations.
+    fn retrieve_timeline(&mut self, id: i32) -> Result<WorkTimeline, Error> {
+        let work = self.retrieve_work(id)?;
+        let events = self.list_events(&EventQuery::new().work_code(work.work_code.as_str()))?;
+        Ok(WorkTimeline::new(&work, events))
+    }
+
+    // `key_hash` is the `auth::hash_key` of the secret, `Error::Conflict` when taken
+    fn create_api_key(
+        &mut self,
+        name: &str,
+        key_hash: &str,
+        scopes: &[Scope],
+    ) -> Result<ApiKey, Error>;
+
+    // `Error::NotFound` when there is no such key or it is revoked
+    fn find_api_key(&mut self, key_hash: &str) -> Result<ApiKey, Error>;
+
+    // oldest first, the revoked ones too
+    fn list_api_keys(&mut self) -> Result<Vec<ApiKey>, Error>;
+
+    // `Error::NotFound` when there is no such key or it is already revoked
+    fn revoke_api_key(&mut self, id: i32) -> Result<(), Error>;
+
+    /// Count one more work created by `client` on `day` (UTC),
+    /// `Error::TooManyRequests` when it already created `limit` works that day.
+    /// Returns how many works it created that day, this one included.
+    fn take_work_quota(&mut self, client: &str, day: NaiveDate, limit: u32) -> Result<u32, Error>;
+
+    // give back what `take_work_quota` counted, e.g. the work was not created after all
+    fn refund_work_quota(&mut self, client: &str, day: NaiveDate) -> Result<(), Error>;
+
+    /// Run `unit` as a single unit of work on a store handed over to it:
+    /// what it stores is committed when it returns `Ok`, rolled back when it returns an error
+    /// (the error is then returned as is).
+    fn in_transaction(
+        &mut self,
+        unit: &mut dyn FnMut(&mut dyn WorkStore) -> Result<(), Error>,
+    ) -> Result<(), Error>;
+
+    /// Check the storage is usable by this binary (e.g. the DB schema is up to date),
+    /// meant to be called once at startup.
+    fn check_schema(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+}
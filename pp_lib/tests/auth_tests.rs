@@ -0,0 +1,64 @@
+mod auth_tests {
+    use pp_lib::auth::{self, Scope, KEY_PREFIX};
+    use pp_lib::service::memory::MemoryStore;
+    use pp_lib::service::store::WorkStore;
+    use pp_lib::Error;
+
+    #[test]
+    fn test_scopes() {
+        // given a comma separated list, with spaces and a duplicate
+        let scopes = Scope::parse_list("work:read, work:write,work:read").unwrap();
+        // then every scope is parsed once
+        assert_eq!(vec![Scope::WorkRead, Scope::WorkWrite], scopes);
+
+        // given unknown or missing scopes, then they are refused
+        for names in &["work:delete", "", " , "] {
+            let res = Scope::parse_list(names);
+            assert!(matches!(res, Err(Error::Validation(_))), "{}", names);
+        }
+    }
+
+    #[test]
+    fn test_mint_and_authenticate() {
+        // given a store with a read-only key and an admin key
+        let mut store = MemoryStore::new();
+        let (reader, secret) = auth::mint_key(&mut store, "reader", &[Scope::WorkRead]).unwrap();
+        let (_, admin_secret) = auth::mint_key(&mut store, "admin", &[Scope::Admin]).unwrap();
+        assert!(secret.starts_with(KEY_PREFIX));
+        assert_ne!(secret, admin_secret);
+
+        // then only the hash is stored
+        let stored = store.list_api_keys().unwrap();
+        assert_eq!(2, stored.len());
+        assert!(!format!("{:?}", stored).contains(secret.as_str()));
+
+        // when presenting the key, then it is recognised with its scopes
+        let bearer = format!("Bearer {}", secret);
+        let key = auth::authenticate(&mut store, Some(bearer.as_str())).unwrap();
+        assert_eq!(reader, key);
+        assert!(key.require(Scope::WorkRead).is_ok());
+        assert!(matches!(
+            key.require(Scope::WorkWrite),
+            Err(Error::Forbidden(_))
+        ));
+        // then the admin is allowed everything
+        let bearer = format!("bearer {}", admin_secret);
+        let key = auth::authenticate(&mut store, Some(bearer.as_str())).unwrap();
+        assert!(Scope::ALL.iter().all(|&scope| key.allows(scope)));
+
+        // when presenting no key, a malformed header or an unknown key, then it is refused
+        for header in &[None, Some(secret.as_str()), Some("Bearer pp_unknown")] {
+            let res = auth::authenticate(&mut store, *header);
+            assert!(matches!(res, Err(Error::Unauthorized(_))), "{:?}", header);
+        }
+
+        // when revoking the key, then it is refused from now on
+        store.revoke_api_key(reader.id).unwrap();
+        let bearer = format!("Bearer {}", secret);
+        let res = auth::authenticate(&mut store, Some(bearer.as_str()));
+        assert!(matches!(res, Err(Error::Unauthorized(_))));
+        let res = store.revoke_api_key(reader.id);
+        assert!(matches!(res, Err(Error::NotFound(_))));
+        assert!(store.list_api_keys().unwrap()[0].revoked_on.is_some());
+    }
+}
@@ -0,0 +1,51 @@
+mod metrics_tests {
+    use std::io::{Read, Write};
+    use std::net::TcpStream;
+
+    use pp_lib::factory;
+    use pp_lib::metrics;
+    use pp_lib::service::store::WorkStore;
+
+    #[test]
+    fn test_db_calls_are_timed() {
+        // given a db client
+        let mut db = factory::db_client().unwrap();
+        let before = metrics::DB_CALL_SECONDS
+            .with_label_values(&["retrieve_work"])
+            .get_sample_count();
+
+        // when calling the DB through the work store
+        let _ = db.retrieve_work(-1);
+
+        // then the call is observed
+        let after = metrics::DB_CALL_SECONDS
+            .with_label_values(&["retrieve_work"])
+            .get_sample_count();
+        assert_eq!(before + 1, after);
+        assert!(metrics::render().contains("pp_db_call_duration_seconds_bucket"));
+    }
+
+    #[test]
+    fn test_serve_metrics() {
+        // given the metrics served on a port
+        metrics::count_message("publish", false);
+        metrics::serve("9199").unwrap();
+
+        // when scraping them
+        let mut stream = TcpStream::connect("127.0.0.1:9199").unwrap();
+        write!(
+            stream,
+            "GET /metrics HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n"
+        )
+        .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+
+        // then they are in the Prometheus text format
+        assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+        assert!(response.contains("text/plain; version=0.0.4"));
+        assert!(
+            response.contains(r#"pp_queue_messages_total{operation="publish",outcome="failed"}"#)
+        );
+    }
+}
@@ -0,0 +1,81 @@
+mod migration_tests {
+    use pp_lib::factory;
+    use pp_lib::migration;
+
+    #[test]
+    fn test_migrate_is_idempotent() {
+        // given a db client
+        let mut db = factory::db_client().unwrap();
+
+        // when migrating (maybe for the first time)
+        let res_m = pp_lib::migrate(&mut db);
+        assert!(res_m.is_ok());
+
+        // then the schema is at the latest version
+        let version = migration::current_version(&mut db).unwrap();
+        assert_eq!(migration::latest_version(), version);
+        assert_eq!(version, migration::check(&mut db).unwrap());
+
+        // when migrating again, then nothing else is applied
+        let res_m = pp_lib::migrate(&mut db);
+        assert_eq!(0, res_m.unwrap());
+
+        // close DB connection
+        let res_db_c = db.close();
+        assert!(res_db_c.is_ok());
+    }
+
+    #[test]
+    fn test_work_code_made_unique() {
+        // given a schema of its own at version 1, with duplicated and long codes
+        let mut db = factory::db_client().unwrap();
+        db.batch_execute(
+            "
+            DROP SCHEMA IF EXISTS migration_v002_test CASCADE;
+            CREATE SCHEMA migration_v002_test;
+            SET search_path TO migration_v002_test;
+            ",
+        )
+        .unwrap();
+        db.batch_execute(migration::MIGRATIONS[0].sql).unwrap();
+        db.batch_execute(
+            "
+            INSERT INTO works (id, work_code, add_up_to) VALUES
+                (1, 'dup', 1), (2, 'dup', 1), (3, 'dup-2', 1),
+                (4, repeat('x', 50), 1), (5, repeat('x', 50), 1);
+            ",
+        )
+        .unwrap();
+
+        // when making the codes unique
+        let res_m = db.batch_execute(migration::MIGRATIONS[1].sql);
+
+        // then every duplicate got a code of its own, within 50 characters
+        let codes: Vec<(i32, String)> = db
+            .query("SELECT id, work_code FROM works ORDER BY id;", &[])
+            .unwrap()
+            .iter()
+            .map(|row| (row.get(0), row.get(1)))
+            .collect();
+        db.batch_execute("DROP SCHEMA migration_v002_test CASCADE;")
+            .unwrap();
+        assert!(res_m.is_ok(), "{:?}", res_m);
+        let expected = vec![
+            (1, String::from("dup")),
+            (2, String::from("dup-2-1")),
+            (3, String::from("dup-2")),
+            (4, "x".repeat(50)),
+            (5, format!("{}-5", "x".repeat(48))),
+        ];
+        assert_eq!(expected, codes);
+    }
+
+    #[test]
+    fn test_migrations_are_sorted() {
+        let versions: Vec<i32> = migration::MIGRATIONS.iter().map(|m| m.version).collect();
+        let mut sorted = versions.clone();
+        sorted.sort_unstable();
+        sorted.dedup();
+        assert_eq!(sorted, versions);
+    }
+}
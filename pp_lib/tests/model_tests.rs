@@ -0,0 +1,213 @@
+mod model_tests {
+    use chrono::Duration;
+    use serde_json::json;
+
+    use pp_lib::factory;
+    use pp_lib::model::{
+        EventValue, EventVariable, NewWork, WorkDemand, WorkPatch, WorkStatus, WorkTimeline,
+        WorkUpdate,
+    };
+    use pp_lib::Error;
+
+    #[test]
+    fn test_event_variable_registry() {
+        // given every registered variable
+        for variable in EventVariable::ALL {
+            // when parsing its name, then it is that variable
+            let parsed: EventVariable = variable.as_str().parse().unwrap();
+            assert_eq!(*variable, parsed);
+            // when serializing it, then it is its name
+            assert_eq!(
+                json!(variable.as_str()),
+                serde_json::to_value(variable).unwrap()
+            );
+        }
+
+        // when parsing an unknown name, then it is refused
+        let res_p = "compute/unknown".parse::<EventVariable>();
+        assert!(matches!(res_p.unwrap_err(), Error::Validation(_)));
+    }
+
+    #[test]
+    fn test_event_value_json() {
+        // given a typed value
+        let value = EventValue::Integer(10);
+
+        // when serializing it, then the type travels along
+        let value_json = serde_json::to_value(&value).unwrap();
+        assert_eq!(json!({"type": "integer", "value": 10}), value_json);
+
+        // when deserializing it, then it is the same value
+        let parsed: EventValue = serde_json::from_value(value_json).unwrap();
+        assert_eq!(value, parsed);
+        assert_eq!(Some(10.0), parsed.as_f64());
+        assert_eq!(None, parsed.as_str());
+    }
+
+    #[test]
+    fn test_new_work_validation() {
+        // given a client request with a chosen code and some metadata
+        let body = r#"{"add_up_to": 10, "work_code": "api-mine", "metadata": {"owner": "me"}}"#;
+        let new_work: NewWork = serde_json::from_str(body).unwrap();
+
+        // when validating it, then it is fine
+        assert!(new_work.validate().is_ok());
+        // when mapping it, then the work carries the same values
+        let work = factory::map_new_work(new_work, "api");
+        assert_eq!("api-mine", work.work_code);
+        assert_eq!(10, work.add_up_to);
+        assert_eq!(Some(json!({"owner": "me"})), work.metadata);
+
+        // given a request without a code, then it gets a random one
+        let new_work: NewWork = serde_json::from_str(r#"{"add_up_to": 10}"#).unwrap();
+        assert!(factory::map_new_work(new_work, "api")
+            .work_code
+            .starts_with("api-"));
+
+        // given a request with the code of a work demand, then it is refused
+        let new_work: NewWork =
+            serde_json::from_str(r#"{"add_up_to": 10, "work_code": "demand-abc"}"#).unwrap();
+        match new_work.validate() {
+            Err(Error::InvalidFields(fields)) => assert_eq!("work_code", fields[0].field),
+            other => panic!("unexpected validation outcome: {:?}", other),
+        }
+
+        // given a request with an unknown field, then it cannot be parsed
+        let res_p = serde_json::from_str::<NewWork>(r#"{"add_up_to": 10, "color": "red"}"#);
+        assert!(res_p.is_err());
+
+        // given a request out of bounds
+        let new_work = NewWork {
+            add_up_to: 0,
+            work_code: Some(String::from("api mine")),
+            metadata: Some(json!(["owner"])),
+        };
+        // when validating it, then every wrong field is reported
+        match new_work.validate() {
+            Err(Error::InvalidFields(fields)) => {
+                let names: Vec<&str> = fields.iter().map(|f| f.field.as_str()).collect();
+                assert_eq!(vec!["add_up_to", "work_code", "metadata"], names);
+            }
+            other => panic!("unexpected validation outcome: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_work_patch_validation() {
+        // given a patch cancelling the work and clearing its metadata
+        let patch: WorkPatch =
+            serde_json::from_str(r#"{"status": "cancelled", "metadata": null}"#).unwrap();
+
+        // when validating it, then it is fine
+        assert!(patch.validate().is_ok());
+        // when mapping it, then the metadata is cleared
+        let update = WorkUpdate::from(patch);
+        assert_eq!(Some(WorkStatus::Cancelled), update.status);
+        assert_eq!(Some(None), update.metadata);
+
+        // given a patch without metadata, then the metadata is left as it is
+        let patch: WorkPatch = serde_json::from_str(r#"{"add_up_to": 10}"#).unwrap();
+        assert_eq!(None, WorkUpdate::from(patch).metadata);
+
+        // given a patch with a status up to the task_consumer, then it is refused
+        for body in &[r#"{"status": "running"}"#, r#"{"status": "succeeded"}"#] {
+            let patch: WorkPatch = serde_json::from_str(body).unwrap();
+            match patch.validate() {
+                Err(Error::InvalidFields(fields)) => assert_eq!("status", fields[0].field),
+                other => panic!("unexpected validation outcome: {:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn test_work_demand() {
+        // given a demand as `task_producer` always sent it, then it still parses
+        let demand: WorkDemand =
+            serde_json::from_str(r#"{"add_up_to": 10, "done": false}"#).unwrap();
+        assert_eq!(None, demand.tracking_id);
+        assert!(demand.validate().is_ok());
+        // when mapping it, then its work gets a random code
+        assert!(factory::map_to_work(demand.clone(), "consumer")
+            .work_code
+            .starts_with("consumer-"));
+
+        // when tracking it, then its work gets the tracking id as code
+        let (tracked, tracking_id) = factory::track_work_demand(demand);
+        assert_eq!(Some(&tracking_id), tracked.tracking_id.as_ref());
+        assert!(factory::is_tracking_id(tracking_id.as_str()));
+        let json = serde_json::to_string(&tracked).unwrap();
+        assert_eq!(
+            tracked,
+            serde_json::from_str::<WorkDemand>(json.as_str()).unwrap()
+        );
+        assert_eq!(
+            tracking_id,
+            factory::map_to_work(tracked, "consumer").work_code
+        );
+        for not_tracking_id in &["demand-", "demand", "api-abc", "demand-a/b", "demandabc"] {
+            assert!(
+                !factory::is_tracking_id(not_tracking_id),
+                "{}",
+                not_tracking_id
+            );
+        }
+
+        // given a client demand out of bounds, then every wrong field is reported
+        let demand = WorkDemand {
+            add_up_to: 0,
+            done: true,
+            tracking_id: Some(String::from("demand-mine")),
+        };
+        match demand.validate() {
+            Err(Error::InvalidFields(fields)) => {
+                let names: Vec<&str> = fields.iter().map(|f| f.field.as_str()).collect();
+                assert_eq!(vec!["add_up_to", "done", "tracking_id"], names);
+            }
+            other => panic!("unexpected validation outcome: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_work_timeline() {
+        // given a work and its events, out of order
+        let work = factory::generate_random_work("testtimeline");
+        let now = factory::now();
+        let at = |variable, ms| {
+            let mut event = factory::new_event(work.work_code.as_str(), variable, None);
+            event.created_on = Some(now + Duration::milliseconds(ms));
+            event
+        };
+        let start = at(EventVariable::ComputeStart, 0);
+        let stop = at(EventVariable::ComputeStop, 200);
+        let mut result = at(EventVariable::ComputeResult, 250);
+        result.value = Some(EventValue::Integer(10));
+
+        // when building its timeline
+        let timeline = WorkTimeline::new(&work, vec![result, stop, start]);
+
+        // then the events are sorted, with the time elapsed since the first one
+        let variables: Vec<EventVariable> =
+            timeline.events.iter().map(|e| e.event.variable).collect();
+        assert_eq!(
+            vec![
+                EventVariable::ComputeStart,
+                EventVariable::ComputeStop,
+                EventVariable::ComputeResult
+            ],
+            variables
+        );
+        let elapsed: Vec<Option<i64>> = timeline.events.iter().map(|e| e.elapsed_ms).collect();
+        assert_eq!(vec![Some(0), Some(200), Some(250)], elapsed);
+        // then the compute duration is derived
+        assert_eq!(Some(200), timeline.compute_duration_ms);
+        // then an event serializes flat
+        let json = serde_json::to_value(&timeline.events[2]).unwrap();
+        assert_eq!(json!("compute/result"), json["variable"]);
+        assert_eq!(json!(250), json["elapsed_ms"]);
+
+        // given no stop yet, then there is no compute duration
+        let start = at(EventVariable::ComputeStart, 0);
+        let timeline = WorkTimeline::new(&work, vec![start]);
+        assert_eq!(None, timeline.compute_duration_ms);
+    }
+}
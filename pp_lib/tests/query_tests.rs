@@ -0,0 +1,175 @@
+mod query_tests {
+    use chrono::{Duration, TimeZone, Utc};
+
+    use pp_lib::factory;
+    use pp_lib::model::WorkStatus;
+    use pp_lib::query::{
+        CodeMatch, SortDirection, SortKey, WorkCursor, WorkQuery, WorkSort, WorkSortField,
+        DEFAULT_PAGE_SIZE, MAX_PAGE_SIZE,
+    };
+    use pp_lib::Error;
+
+    #[test]
+    fn test_work_query_from_query_string() {
+        // given a query string with every filter, some percent-encoded
+        let cursor = WorkCursor {
+            field: WorkSortField::CreatedOn,
+            key: SortKey::Time(Utc.timestamp_opt(1634115736, 123_456_000).unwrap()),
+            id: 42,
+        };
+        let query_string = format!(
+            "work_code=api%2Dfoo&done=false&status=running\
+            &min_add_up_to=2&max_add_up_to=20\
+            &created_after=1634115736&created_before=2021-10-14T10%3A00%3A00%2B01%3A00\
+            &sort=-created_on&limit=10&cursor={}",
+            cursor.encode()
+        );
+
+        // when parsing it
+        let query = WorkQuery::from_query_string(query_string.as_str()).unwrap();
+
+        // then every filter is set
+        assert_eq!(
+            Some(CodeMatch::Prefix(String::from("api-foo"))),
+            query.work_code
+        );
+        assert_eq!(Some(false), query.done);
+        assert_eq!(Some(WorkStatus::Running), query.status);
+        assert_eq!(Some(2), query.min_add_up_to);
+        assert_eq!(Some(20), query.max_add_up_to);
+        assert_eq!(
+            Some(Utc.timestamp_opt(1634115736, 0).unwrap()),
+            query.created_after
+        );
+        assert_eq!(
+            Some(Utc.timestamp_opt(1634202000, 0).unwrap()),
+            query.created_before
+        );
+        assert_eq!(
+            WorkSort {
+                field: WorkSortField::CreatedOn,
+                direction: SortDirection::Desc
+            },
+            query.sort
+        );
+        assert_eq!(Some(10), query.limit);
+        assert_eq!(Some(cursor), query.after);
+
+        // given an empty query string, then it matches every work, one page at a time
+        let query = WorkQuery::from_query_string("").unwrap();
+        assert_eq!(WorkQuery::new().limit(DEFAULT_PAGE_SIZE), query);
+        // given a huge page, then it is capped
+        let query = WorkQuery::from_query_string("limit=1000000").unwrap();
+        assert_eq!(Some(MAX_PAGE_SIZE), query.limit);
+        // given any other sort field, then it is taken
+        let query = WorkQuery::from_query_string("sort=-add_up_to").unwrap();
+        assert_eq!(
+            WorkSort {
+                field: WorkSortField::AddUpTo,
+                direction: SortDirection::Desc
+            },
+            query.sort
+        );
+    }
+
+    #[test]
+    fn test_work_query_from_invalid_query_string() {
+        // given unknown, repeated or invalid parameters
+        for query_string in &[
+            "colour=red",
+            "work_code=a&work_code=b",
+            "done=maybe",
+            "status=sleeping",
+            "min_add_up_to=ten",
+            "created_after=yesterday",
+            "sort=colour",
+            "sort=-",
+            "limit=-1",
+            "limit=0",
+            "cursor=30",
+            "cursor=OjE6Mg",
+        ] {
+            // when parsing them, then they are refused
+            let res = WorkQuery::from_query_string(query_string);
+            assert!(
+                matches!(res, Err(Error::Validation(_))),
+                "{} should be refused",
+                query_string
+            );
+        }
+
+        // given the cursor of a page sorted on another field, then it is refused
+        let cursor = WorkCursor {
+            field: WorkSortField::CreatedOn,
+            key: SortKey::Time(factory::now()),
+            id: 1,
+        };
+        let query_string = format!("sort=add_up_to&cursor={}", cursor.encode());
+        let res = WorkQuery::from_query_string(query_string.as_str());
+        assert!(matches!(res, Err(Error::Validation(_))));
+    }
+
+    #[test]
+    fn test_work_query_done() {
+        // given a running and a cancelled work
+        let mut work = factory::generate_random_work("testdone");
+        work.status = WorkStatus::Running;
+        let mut cancelled = work.clone();
+        cancelled.status = WorkStatus::Cancelled;
+
+        // when filtering on done, then only the final status matches
+        let query = WorkQuery::new().done(true);
+        assert!(!query.matches(&work));
+        assert!(query.matches(&cancelled));
+        let query = WorkQuery::new().done(false);
+        assert!(query.matches(&work));
+        assert!(!query.matches(&cancelled));
+    }
+
+    #[test]
+    fn test_work_cursor() {
+        // given a cursor
+        let now = factory::now();
+        let cursor = WorkCursor {
+            field: WorkSortField::CreatedOn,
+            key: SortKey::Time(now),
+            id: 7,
+        };
+        // when encoding and decoding it, then it is the same
+        assert_eq!(
+            cursor,
+            WorkCursor::decode(cursor.encode().as_str()).unwrap()
+        );
+
+        // given works around the cursor
+        let mut before = factory::generate_random_work("testcursor");
+        before.created_on = Some(now - Duration::seconds(1));
+        before.id = 100;
+        let mut tie = before.clone();
+        tie.created_on = Some(now);
+        tie.id = 8;
+
+        // when paging forward, then only the later ones match
+        let query = WorkQuery::new().after(cursor);
+        assert!(!query.matches(&before));
+        assert!(query.matches(&tie));
+        // when paging backward, then only the earlier ones match
+        let query = query.sort_by(WorkSortField::CreatedOn, SortDirection::Desc);
+        assert!(query.matches(&before));
+        assert!(!query.matches(&tie));
+
+        // given the cursor of a work sorted on add_up_to
+        let cursor = WorkCursor::from_work(&before, WorkSortField::AddUpTo).unwrap();
+        assert_eq!(SortKey::Int(before.add_up_to), cursor.key);
+        // when encoding and decoding it, then it is the same
+        assert_eq!(
+            cursor,
+            WorkCursor::decode(cursor.encode().as_str()).unwrap()
+        );
+        // when paging in another order with it, then it is refused
+        let query = WorkQuery::new().after(cursor);
+        assert!(matches!(query.check_cursor(), Err(Error::Validation(_))));
+        let query = query.sort_by(WorkSortField::AddUpTo, SortDirection::Asc);
+        assert!(query.check_cursor().is_ok());
+    }
+}
@@ -1,11 +1,18 @@
 mod db_tests {
+    use chrono::{Duration, Utc};
     use env_logger::Env;
+    use serde_json::json;
     use std::{thread, time};
 
+    use pp_lib::auth::{self, Scope};
     use pp_lib::factory;
-    use pp_lib::model;
-    use pp_lib::model::Work;
+    use pp_lib::model::{EventValue, EventVariable, NewWork, Work, WorkStatus, WorkUpdate};
+    use pp_lib::query::{
+        EventQuery, SortDirection, WorkCursor, WorkPage, WorkQuery, WorkSortField,
+    };
     use pp_lib::service;
+    use pp_lib::service::store::WorkStore;
+    use pp_lib::Error;
 
     #[test]
     fn test_crud_work() {
@@ -15,7 +22,7 @@ mod db_tests {
         env_logger::init_from_env(env);
 
         // given a db client
-        let mut db = factory::db_client();
+        let mut db = factory::db_client().unwrap();
         // given a work request
         let work: Work = factory::generate_random_work("testdb");
 
@@ -24,7 +31,7 @@ mod db_tests {
         let res_c = service::db::create_work(&mut db, work.clone());
 
         // then we get the expected work struct
-        assert!(!res_c.is_err());
+        assert!(res_c.is_ok());
         let work_output = res_c.unwrap();
         assert_eq!(work_output.work_code, work.work_code);
         assert_eq!(work_output.add_up_to, work.add_up_to);
@@ -35,33 +42,100 @@ mod db_tests {
         let res_r = service::db::retrieve_work(&mut db, work_output.id);
 
         // then we get that same work struct
-        assert!(!res_r.is_err());
+        assert!(res_r.is_ok());
         let work_retrieved = res_r.unwrap();
         assert_eq!(work_output, work_retrieved);
         println!("\n\n>>> RETRIEVED: {:?}\n\n", work_retrieved);
 
         // SEARCH
         // when we search for that work
-        let res_s = service::db::search_work(&mut db, work_output.work_code.as_str());
+        let query = WorkQuery::new().work_code_prefix(work_output.work_code.as_str());
+        let res_s = service::db::search_work(&mut db, &query);
 
         // then we get that same work struct
-        assert!(!res_s.is_err());
+        assert!(res_s.is_ok());
         let res_list = res_s.unwrap();
-        let work_searched = res_list.get(0).unwrap();
+        let work_searched = res_list.first().unwrap();
         assert_eq!(work_output, *work_searched);
         println!("\n\n>>> SEARCHED: {:?}\n\n", work_searched);
 
         // UPDATE
         thread::sleep(time::Duration::from_millis(500)); // so we can compare the `updated_on` field
-        let res_u = service::db::update_work_done(&mut db, work_output.id);
+        let res_u = service::db::update_work_status(&mut db, work_output.id, WorkStatus::Running);
         assert!(res_u.is_ok());
 
         // then check the `updated_on` field is greater than `created_on`
         let res_r = service::db::retrieve_work(&mut db, work_output.id);
         let work_updated = res_r.unwrap();
         println!("\n\n>>> UPDATED: {:?}\n\n", work_updated);
-        assert!(work_updated.done);
+        assert_eq!(WorkStatus::Running, work_updated.status);
         assert!(work_updated.updated_on > work_updated.created_on);
+        assert_eq!(None, work_updated.result);
+
+        // COMPLETE
+        // when storing the outcome of the computation
+        let result = factory::new_work_result(42, work_output.created_on.unwrap());
+        let res_cw = service::db::complete_work(&mut db, work_output.id, &result);
+        assert!(res_cw.is_ok());
+
+        // then it comes back with the work
+        let work_completed = service::db::retrieve_work(&mut db, work_output.id).unwrap();
+        assert_eq!(WorkStatus::Succeeded, work_completed.status);
+        assert_eq!(Some(42), work_completed.result);
+        assert_eq!(Some(result.started_at), work_completed.started_at);
+        assert_eq!(Some(result.finished_at), work_completed.finished_at);
+        assert_eq!(Some(result.duration_ms()), work_completed.duration_ms);
+        assert!(work_completed.duration_ms.unwrap() >= 500);
+
+        // close DB connection
+        let res_db_c = db.close();
+        assert!(res_db_c.is_ok());
+    }
+
+    #[test]
+    fn test_search_work_query() {
+        // given a db client
+        let mut db = factory::db_client().unwrap();
+        // given a few works sharing a random prefix
+        let prefix = format!("testq{}", factory::rand_alphanumeric_any(6));
+        for add_up_to in [5, 10, 15] {
+            let work = factory::new_work(format!("{}-{}", prefix, add_up_to).as_str(), add_up_to);
+            service::db::create_work(&mut db, work).unwrap();
+        }
+
+        // when filtering on the prefix and a range, sorting and limiting
+        let query = WorkQuery::new()
+            .work_code_prefix(prefix.as_str())
+            .status(WorkStatus::Pending)
+            .add_up_to_between(Some(6), None)
+            .sort_by(WorkSortField::AddUpTo, SortDirection::Desc)
+            .limit(1);
+        let works = service::db::search_work(&mut db, &query).unwrap();
+
+        // then only the biggest one comes back
+        assert_eq!(1, works.len());
+        assert_eq!(15, works[0].add_up_to);
+
+        // when the paging goes past the end
+        let works = service::db::search_work(&mut db, &query.clone().offset(2)).unwrap();
+        assert!(works.is_empty());
+
+        // when filtering on the works still to do, then they are all there
+        let query = WorkQuery::new().work_code_prefix(prefix.as_str());
+        let works = service::db::search_work(&mut db, &query.clone().done(false)).unwrap();
+        assert_eq!(3, works.len());
+        let works = service::db::search_work(&mut db, &query.done(true)).unwrap();
+        assert!(works.is_empty());
+
+        // when the search string carries SQL or LIKE wildcards
+        for evil in ["' OR '1'='1", "%", "_", "\\"] {
+            let query = WorkQuery::new().work_code_contains(evil);
+            let res_s = service::db::search_work(&mut db, &query);
+
+            // then they are matched literally
+            assert!(res_s.is_ok());
+            assert!(res_s.unwrap().iter().all(|w| w.work_code.contains(evil)));
+        }
 
         // close DB connection
         let res_db_c = db.close();
@@ -71,9 +145,11 @@ mod db_tests {
     #[test]
     fn test_crud_event() {
         // given a db client
-        let mut db = factory::db_client();
-        // given an event
-        let event = factory::new_event("my_work_code", model::VAR_COMPUTE_START, "");
+        let mut db = factory::db_client().unwrap();
+        // given an event for an existing work
+        let work =
+            service::db::create_work(&mut db, factory::generate_random_work("testdb")).unwrap();
+        let event = factory::new_event(work.work_code.as_str(), EventVariable::ComputeStart, None);
 
         // when flushing the event to DB
         let res_ef = service::db::create_event(&mut db, event);
@@ -81,6 +157,431 @@ mod db_tests {
         // then
         assert!(res_ef.is_ok());
 
+        // when flushing events with every kind of value
+        let values = vec![
+            EventValue::Integer(-42),
+            EventValue::Float(2.5),
+            EventValue::Bool(true),
+            EventValue::Text(String::from("it's \"quoted\"")),
+            EventValue::Json(json!({"partial_sums": [1, 3, 6]})),
+        ];
+        for value in values.iter() {
+            let event = factory::new_event(
+                work.work_code.as_str(),
+                EventVariable::ComputeResult,
+                Some(value.clone()),
+            );
+            service::db::create_event(&mut db, event).unwrap();
+        }
+
+        // then they come back with their type
+        let query = EventQuery::new()
+            .work_code(work.work_code.as_str())
+            .variable(EventVariable::ComputeResult);
+        let events = service::db::list_events(&mut db, &query).unwrap();
+        let stored: Vec<EventValue> = events.into_iter().filter_map(|e| e.value).collect();
+        assert_eq!(values, stored);
+
+        // close DB connection
+        let res_db_c = db.close();
+        assert!(res_db_c.is_ok());
+    }
+
+    #[test]
+    fn test_list_events() {
+        // given a db client
+        let mut db = factory::db_client().unwrap();
+        // given the events of a computation
+        let work_code = format!("testev-{}", factory::rand_alphanumeric());
+        service::db::create_work(&mut db, factory::new_work(work_code.as_str(), 5)).unwrap();
+        let since = Utc::now() - Duration::seconds(1);
+        for (variable, value) in [
+            (EventVariable::ComputeStart, None),
+            (EventVariable::ComputeStop, None),
+            (EventVariable::ComputeResult, Some(EventValue::Integer(10))),
+        ] {
+            let event = factory::new_event(work_code.as_str(), variable, value);
+            service::db::create_event(&mut db, event).unwrap();
+        }
+
+        // when listing the events of that work
+        let query = EventQuery::new()
+            .work_code(work_code.as_str())
+            .created_between(Some(since), None);
+        let events = service::db::list_events(&mut db, &query).unwrap();
+
+        // then they come back in chronological order
+        let variables: Vec<EventVariable> = events.iter().map(|e| e.variable).collect();
+        assert_eq!(
+            vec![
+                EventVariable::ComputeStart,
+                EventVariable::ComputeStop,
+                EventVariable::ComputeResult
+            ],
+            variables
+        );
+
+        // when filtering on the variable
+        let query = query.variable(EventVariable::ComputeResult);
+        let events = service::db::list_events(&mut db, &query).unwrap();
+        assert_eq!(1, events.len());
+        assert_eq!(Some(10), events[0].value.as_ref().and_then(|v| v.as_i64()));
+
+        // when paging past the end
+        let events = service::db::list_events(&mut db, &query.offset(1).limit(10)).unwrap();
+        assert!(events.is_empty());
+
+        // close DB connection
+        let res_db_c = db.close();
+        assert!(res_db_c.is_ok());
+    }
+
+    #[test]
+    fn test_work_code_integrity() {
+        // given a db client
+        let mut db = factory::db_client().unwrap();
+        // given an existing work
+        let work = factory::generate_random_work("testint");
+        let work = service::db::create_work(&mut db, work).unwrap();
+
+        // when creating another work with the same code
+        let res_c = service::db::create_work(&mut db, work.clone());
+        // then it is a conflict
+        assert!(matches!(res_c.unwrap_err(), Error::Conflict(_)));
+
+        // when creating it with the retries
+        let res_c = db.create_work_retrying(work.clone());
+        // then it gets a new code with the same prefix
+        let other = res_c.unwrap();
+        assert_ne!(work.work_code, other.work_code);
+        assert!(other.work_code.starts_with("testint-"));
+
+        // when creating an event for an unknown work, then it is refused
+        let event = factory::new_event("testint-unknown", EventVariable::ComputeStart, None);
+        assert!(service::db::create_event(&mut db, event).is_err());
+
+        // when deleting a work with events, then it is refused
+        let event = factory::new_event(work.work_code.as_str(), EventVariable::ComputeStart, None);
+        service::db::create_event(&mut db, event).unwrap();
+        let res_d = service::db::delete_work(&mut db, work.id);
+        assert!(matches!(res_d.unwrap_err(), Error::Conflict(_)));
+
+        // when deleting a work without events, then it is gone
+        assert!(service::db::delete_work(&mut db, other.id).is_ok());
+        let res_d = service::db::delete_work(&mut db, other.id);
+        assert!(matches!(res_d.unwrap_err(), Error::NotFound(_)));
+
+        // close DB connection
+        let res_db_c = db.close();
+        assert!(res_db_c.is_ok());
+    }
+
+    #[test]
+    fn test_transaction() {
+        // given a db client
+        let mut db = factory::db_client().unwrap();
+        // given an existing work
+        let taken =
+            service::db::create_work(&mut db, factory::generate_random_work("testtx")).unwrap();
+
+        // when a unit of work fails midway
+        let code = format!("testtx-{}", factory::rand_alphanumeric());
+        let res_t = db.in_transaction(&mut |tx| {
+            let work = tx.create_work(factory::new_work(code.as_str(), 5))?;
+            tx.create_event(factory::new_event(
+                work.work_code.as_str(),
+                EventVariable::ComputeStart,
+                None,
+            ))?;
+            tx.update_work_status(-1, WorkStatus::Running)
+        });
+
+        // then its error comes back and nothing is left behind
+        assert!(matches!(res_t.unwrap_err(), Error::NotFound(_)));
+        let query = WorkQuery::new().work_code_prefix(code.as_str());
+        assert!(service::db::search_work(&mut db, &query)
+            .unwrap()
+            .is_empty());
+
+        // when a unit of work succeeds despite a `work_code` collision
+        let mut stored: Option<Work> = None;
+        let res_t = db.in_transaction(&mut |tx| {
+            let work = tx.create_work_retrying(taken.clone())?;
+            tx.create_event(factory::new_event(
+                work.work_code.as_str(),
+                EventVariable::ComputeStart,
+                None,
+            ))?;
+            tx.update_work_status(work.id, WorkStatus::Running)?;
+            stored = Some(work);
+            Ok(())
+        });
+
+        // then all of it is stored
+        assert!(res_t.is_ok());
+        let work = stored.unwrap();
+        assert_ne!(taken.work_code, work.work_code);
+        let work_stored = service::db::retrieve_work(&mut db, work.id).unwrap();
+        assert_eq!(WorkStatus::Running, work_stored.status);
+        let query = EventQuery::new().work_code(work.work_code.as_str());
+        assert_eq!(1, service::db::list_events(&mut db, &query).unwrap().len());
+
+        // close DB connection
+        let res_db_c = db.close();
+        assert!(res_db_c.is_ok());
+    }
+
+    #[test]
+    fn test_work_status_transitions() {
+        // given a db client
+        let mut db = factory::db_client().unwrap();
+        // given a running work
+        let work = factory::generate_random_work("teststatus");
+        let work = service::db::create_work(&mut db, work).unwrap();
+        assert_eq!(WorkStatus::Pending, work.status);
+        service::db::update_work_status(&mut db, work.id, WorkStatus::Queued).unwrap();
+        service::db::update_work_status(&mut db, work.id, WorkStatus::Running).unwrap();
+
+        // when going back to the queue, then it is refused
+        let res_u = service::db::update_work_status(&mut db, work.id, WorkStatus::Queued);
+        assert!(matches!(res_u.unwrap_err(), Error::Conflict(_)));
+
+        // when it fails
+        service::db::update_work_status(&mut db, work.id, WorkStatus::Failed).unwrap();
+
+        // then it can be neither completed nor cancelled
+        let result = factory::new_work_result(42, work.created_on.unwrap());
+        let res_cw = service::db::complete_work(&mut db, work.id, &result);
+        assert!(matches!(res_cw.unwrap_err(), Error::Conflict(_)));
+        let res_u = service::db::update_work_status(&mut db, work.id, WorkStatus::Cancelled);
+        assert!(matches!(res_u.unwrap_err(), Error::Conflict(_)));
+        let work_stored = service::db::retrieve_work(&mut db, work.id).unwrap();
+        assert_eq!(WorkStatus::Failed, work_stored.status);
+        assert_eq!(None, work_stored.result);
+
+        // when the work does not exist, then it is not found
+        let res_u = service::db::update_work_status(&mut db, -1, WorkStatus::Running);
+        assert!(matches!(res_u.unwrap_err(), Error::NotFound(_)));
+
+        // when searching by its status, then it is found
+        let query = WorkQuery::new()
+            .work_code_prefix(work.work_code.as_str())
+            .status(WorkStatus::Failed);
+        assert_eq!(1, service::db::search_work(&mut db, &query).unwrap().len());
+
+        // close DB connection
+        let res_db_c = db.close();
+        assert!(res_db_c.is_ok());
+    }
+
+    #[test]
+    fn test_work_metadata() {
+        // given a db client
+        let mut db = factory::db_client().unwrap();
+        // given a work with some metadata
+        let new_work = NewWork {
+            add_up_to: 5,
+            work_code: None,
+            metadata: Some(json!({"owner": "me", "tags": ["a", "b"]})),
+        };
+        let work = factory::map_new_work(new_work, "testmeta");
+
+        // when storing and retrieving it
+        let created = service::db::create_work(&mut db, work.clone()).unwrap();
+        let retrieved = service::db::retrieve_work(&mut db, created.id).unwrap();
+
+        // then the metadata is the same
+        assert_eq!(work.metadata, retrieved.metadata);
+
+        // close DB connection
+        let res_db_c = db.close();
+        assert!(res_db_c.is_ok());
+    }
+
+    #[test]
+    fn test_update_and_delete_work() {
+        // given a db client
+        let mut db = factory::db_client().unwrap();
+        // given a pending work with some metadata
+        let mut work = factory::generate_random_work("testupd");
+        work.metadata = Some(json!({"owner": "me"}));
+        let work = service::db::create_work(&mut db, work).unwrap();
+
+        // when changing its bound only
+        let update = WorkUpdate {
+            add_up_to: Some(7),
+            ..WorkUpdate::default()
+        };
+        let updated = service::db::update_work(&mut db, work.id, &update).unwrap();
+        // then the other fields stay the same
+        assert_eq!(7, updated.add_up_to);
+        assert_eq!(work.metadata, updated.metadata);
+        assert_eq!(
+            updated,
+            service::db::retrieve_work(&mut db, work.id).unwrap()
+        );
+
+        // when clearing its metadata and cancelling it
+        let update = WorkUpdate {
+            status: Some(WorkStatus::Cancelled),
+            metadata: Some(None),
+            ..WorkUpdate::default()
+        };
+        let updated = service::db::update_work(&mut db, work.id, &update).unwrap();
+        assert_eq!(WorkStatus::Cancelled, updated.status);
+        assert_eq!(None, updated.metadata);
+
+        // when changing a cancelled work, then it is refused
+        let update = WorkUpdate {
+            add_up_to: Some(8),
+            ..WorkUpdate::default()
+        };
+        let res_u = service::db::update_work(&mut db, work.id, &update);
+        assert!(matches!(res_u.unwrap_err(), Error::Conflict(_)));
+
+        // when soft deleting it, then it is gone for the clients
+        service::db::soft_delete_work(&mut db, work.id).unwrap();
+        let res_r = service::db::retrieve_work(&mut db, work.id);
+        assert!(matches!(res_r.unwrap_err(), Error::NotFound(_)));
+        let query = WorkQuery::new().work_code_prefix(work.work_code.as_str());
+        assert!(service::db::search_work(&mut db, &query)
+            .unwrap()
+            .is_empty());
+        let res_u = service::db::update_work(&mut db, work.id, &update);
+        assert!(matches!(res_u.unwrap_err(), Error::NotFound(_)));
+
+        // when hard deleting it, then the row is gone
+        service::db::delete_work(&mut db, work.id).unwrap();
+        let res_d = service::db::delete_work(&mut db, work.id);
+        assert!(matches!(res_d.unwrap_err(), Error::NotFound(_)));
+
+        // close DB connection
+        let res_db_c = db.close();
+        assert!(res_db_c.is_ok());
+    }
+
+    #[test]
+    fn test_search_work_page() {
+        // given a db client with a few works sharing a random prefix
+        let mut db = factory::db_client().unwrap();
+        let prefix = format!("testpage{}", factory::rand_alphanumeric_any(6));
+        let mut ids: Vec<i32> = Vec::new();
+        for add_up_to in 1..=5 {
+            let work = factory::new_work(format!("{}-{}", prefix, add_up_to).as_str(), add_up_to);
+            ids.push(service::db::create_work(&mut db, work).unwrap().id);
+        }
+
+        // when following the pages of 2 works
+        let mut query = WorkQuery::new().work_code_prefix(prefix.as_str()).limit(2);
+        let mut pages: Vec<WorkPage> = Vec::new();
+        loop {
+            let page = db.search_work_page(&query).unwrap();
+            let next_cursor = page.next_cursor.clone();
+            pages.push(page);
+            match next_cursor {
+                Some(cursor) => query = query.after(WorkCursor::decode(cursor.as_str()).unwrap()),
+                None => break,
+            }
+        }
+
+        // then every work comes back once, in the creation order
+        assert_eq!(3, pages.len());
+        let paged: Vec<i32> = pages
+            .iter()
+            .flat_map(|p| p.items.iter().map(|w| w.id))
+            .collect();
+        assert_eq!(ids, paged);
+
+        // when paging backward from the last work, then the others come back newest first
+        let last = pages[2].items[0].clone();
+        let query = WorkQuery::new()
+            .work_code_prefix(prefix.as_str())
+            .sort_by(WorkSortField::CreatedOn, SortDirection::Desc)
+            .after(WorkCursor::from_work(&last, WorkSortField::CreatedOn).unwrap());
+        let page = db.search_work_page(&query).unwrap();
+        let backward: Vec<i32> = page.items.iter().map(|w| w.id).collect();
+        assert_eq!(vec![ids[3], ids[2], ids[1], ids[0]], backward);
+        assert_eq!(None, page.next_cursor);
+
+        // when following the pages of 2 works sorted on -add_up_to
+        let mut query = WorkQuery::new()
+            .work_code_prefix(prefix.as_str())
+            .sort_by(WorkSortField::AddUpTo, SortDirection::Desc)
+            .limit(2);
+        let mut paged: Vec<i32> = Vec::new();
+        loop {
+            let page = db.search_work_page(&query).unwrap();
+            paged.extend(page.items.iter().map(|w| w.id));
+            match page.next_cursor {
+                Some(cursor) => query = query.after(WorkCursor::decode(cursor.as_str()).unwrap()),
+                None => break,
+            }
+        }
+        // then every work comes back once, the largest add_up_to first
+        let mut expected = ids.clone();
+        expected.reverse();
+        assert_eq!(expected, paged);
+
+        // when paging on created_on with that cursor, then it is refused
+        let query = query.sort_by(WorkSortField::CreatedOn, SortDirection::Desc);
+        let res_p = db.search_work_page(&query);
+        assert!(matches!(res_p.unwrap_err(), Error::Validation(_)));
+
+        // close DB connection
+        let res_db_c = db.close();
+        assert!(res_db_c.is_ok());
+    }
+
+    #[test]
+    fn test_api_keys() {
+        // given a db client
+        let mut db = factory::db_client().unwrap();
+
+        // when minting a key, then it is found by the hash of its secret
+        let (key, secret) =
+            auth::mint_key(&mut db, "testdb", &[Scope::WorkRead, Scope::WorkWrite]).unwrap();
+        let hash = auth::hash_key(secret.as_str());
+        assert_eq!(
+            key,
+            service::db::find_api_key(&mut db, hash.as_str()).unwrap()
+        );
+        assert!(service::db::list_api_keys(&mut db).unwrap().contains(&key));
+        // when storing the same hash again, then it is refused
+        let res = service::db::create_api_key(&mut db, "testdb", hash.as_str(), &[Scope::Admin]);
+        assert!(matches!(res.unwrap_err(), Error::Conflict(_)));
+
+        // when revoking it, then it is not found anymore
+        service::db::revoke_api_key(&mut db, key.id).unwrap();
+        let res_f = service::db::find_api_key(&mut db, hash.as_str());
+        assert!(matches!(res_f.unwrap_err(), Error::NotFound(_)));
+        let res_r = service::db::revoke_api_key(&mut db, key.id);
+        assert!(matches!(res_r.unwrap_err(), Error::NotFound(_)));
+
+        // close DB connection
+        let res_db_c = db.close();
+        assert!(res_db_c.is_ok());
+    }
+
+    #[test]
+    fn test_work_quota() {
+        // given a db client, a new client and a daily quota of 2 works
+        let mut db = factory::db_client().unwrap();
+        let client = format!("testquota-{}", factory::rand_alphanumeric_any(8));
+        let today = Utc::now().date_naive();
+
+        // when the client creates works, then it is counted until the quota is reached
+        assert_eq!(1, db.take_work_quota(client.as_str(), today, 2).unwrap());
+        assert_eq!(2, db.take_work_quota(client.as_str(), today, 2).unwrap());
+        let res = db.take_work_quota(client.as_str(), today, 2);
+        assert!(matches!(res.unwrap_err(), Error::TooManyRequests(_)));
+        // then without quota it cannot create any
+        let res = db.take_work_quota(client.as_str(), today.succ_opt().unwrap(), 0);
+        assert!(matches!(res.unwrap_err(), Error::TooManyRequests(_)));
+
+        // when a work is given back, then the client can create another one
+        db.refund_work_quota(client.as_str(), today).unwrap();
+        assert_eq!(2, db.take_work_quota(client.as_str(), today, 2).unwrap());
+
         // close DB connection
         let res_db_c = db.close();
         assert!(res_db_c.is_ok());
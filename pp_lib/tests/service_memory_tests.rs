@@ -0,0 +1,548 @@
+mod memory_tests {
+    use std::{thread, time};
+
+    use chrono::{Duration, Utc};
+    use serde_json::json;
+
+    use pp_lib::factory;
+    use pp_lib::model::{EventVariable, Work, WorkDemand, WorkStatus, WorkUpdate};
+    use pp_lib::query::{
+        EventQuery, SortDirection, WorkCursor, WorkPage, WorkQuery, WorkSortField,
+    };
+    use pp_lib::service::memory::MemoryStore;
+    use pp_lib::service::pool::StorePool;
+    use pp_lib::service::store::WorkStore;
+    use pp_lib::Error;
+
+    #[test]
+    fn test_crud_work() {
+        // given an in-memory store
+        let mut store = MemoryStore::new();
+        // given a work request
+        let work: Work = factory::generate_random_work("testmem");
+
+        // CREATE
+        let res_c = store.create_work(work.clone());
+        assert!(res_c.is_ok());
+        let work_output = res_c.unwrap();
+        assert!(work_output.id > 0);
+        assert_eq!(work_output.work_code, work.work_code);
+        assert_eq!(work_output.add_up_to, work.add_up_to);
+
+        // RETRIEVE
+        let res_r = store.retrieve_work(work_output.id);
+        assert!(res_r.is_ok());
+        assert_eq!(work_output, res_r.unwrap());
+
+        // SEARCH
+        let res_s =
+            store.search_work(&WorkQuery::new().work_code_prefix(work_output.work_code.as_str()));
+        assert!(res_s.is_ok());
+        assert_eq!(vec![work_output.clone()], res_s.unwrap());
+
+        // UPDATE
+        thread::sleep(time::Duration::from_millis(10)); // so we can compare the `updated_on` field
+        let res_u = store.update_work_status(work_output.id, WorkStatus::Running);
+        assert!(res_u.is_ok());
+        let work_updated = store.retrieve_work(work_output.id).unwrap();
+        assert_eq!(WorkStatus::Running, work_updated.status);
+        assert!(work_updated.updated_on > work_updated.created_on);
+
+        // COMPLETE
+        let result = factory::new_work_result(42, work_output.created_on.unwrap());
+        assert!(store.complete_work(work_output.id, &result).is_ok());
+        let work_completed = store.retrieve_work(work_output.id).unwrap();
+        assert_eq!(WorkStatus::Succeeded, work_completed.status);
+        assert_eq!(Some(42), work_completed.result);
+        assert_eq!(Some(result.finished_at), work_completed.finished_at);
+        assert_eq!(Some(result.duration_ms()), work_completed.duration_ms);
+        let res_cw = store.complete_work(-1, &result);
+        assert!(matches!(res_cw.unwrap_err(), Error::NotFound(_)));
+    }
+
+    #[test]
+    fn test_search_work_query() {
+        // given a few works
+        let mut store = MemoryStore::new();
+        for (code, add_up_to) in [("api-aaa", 5), ("api-bbb", 10), ("consumer-ccc", 15)] {
+            store
+                .create_work(factory::new_work(code, add_up_to))
+                .unwrap();
+        }
+        store.update_work_status(2, WorkStatus::Running).unwrap();
+
+        // when filtering on the prefix and the status
+        let query = WorkQuery::new()
+            .work_code_prefix("api-")
+            .status(WorkStatus::Pending);
+        let works = store.search_work(&query).unwrap();
+        // then
+        assert_eq!(vec!["api-aaa"], codes(&works));
+
+        // when matching a substring, sorting and paging
+        let query = WorkQuery::new()
+            .work_code_contains("c")
+            .sort_by(WorkSortField::AddUpTo, SortDirection::Desc);
+        let works = store.search_work(&query).unwrap();
+        assert_eq!(vec!["consumer-ccc"], codes(&works));
+        let query = WorkQuery::new()
+            .add_up_to_between(Some(6), Some(20))
+            .sort_by(WorkSortField::AddUpTo, SortDirection::Desc)
+            .offset(1)
+            .limit(5);
+        let works = store.search_work(&query).unwrap();
+        assert_eq!(vec!["api-bbb"], codes(&works));
+
+        // when the time window excludes everything
+        let query = WorkQuery::new().created_between(Some(Utc::now() + Duration::hours(1)), None);
+        assert!(store.search_work(&query).unwrap().is_empty());
+    }
+
+    fn codes(works: &[Work]) -> Vec<&str> {
+        works.iter().map(|w| w.work_code.as_str()).collect()
+    }
+
+    #[test]
+    fn test_retrieve_missing_work() {
+        let mut store = MemoryStore::new();
+
+        let res_r = store.retrieve_work(42);
+
+        assert!(matches!(res_r.unwrap_err(), Error::NotFound(_)));
+    }
+
+    #[test]
+    fn test_find_work_by_code() {
+        // given works whose codes share a prefix
+        let mut store = MemoryStore::new();
+        store
+            .create_work(factory::new_work("demand-abc", 3))
+            .unwrap();
+        let created = store
+            .create_work(factory::new_work("demand-ab", 3))
+            .unwrap();
+
+        // when finding one by its code, then only the exact code matches
+        let found = store.find_work_by_code("demand-ab").unwrap();
+        assert_eq!(created, found);
+        let res_f = store.find_work_by_code("demand-a");
+        assert!(matches!(res_f.unwrap_err(), Error::NotFound(_)));
+    }
+
+    #[test]
+    fn test_crud_event() {
+        let mut store = MemoryStore::new();
+        store
+            .create_work(factory::new_work("my_work_code", 5))
+            .unwrap();
+        let event = factory::new_event("my_work_code", EventVariable::ComputeStart, None);
+
+        let res_ef = store.create_event(event);
+
+        assert!(res_ef.is_ok());
+    }
+
+    #[test]
+    fn test_work_code_integrity() {
+        // given an existing work with an event
+        let mut store = MemoryStore::new();
+        let work = store.create_work(factory::new_work("api-aaa", 5)).unwrap();
+        let event = factory::new_event("api-aaa", EventVariable::ComputeStart, None);
+        store.create_event(event).unwrap();
+
+        // then the same code is a conflict, unless retrying with a new suffix
+        let res_c = store.create_work(factory::new_work("api-aaa", 7));
+        assert!(matches!(res_c.unwrap_err(), Error::Conflict(_)));
+        let other = store.create_work_retrying(factory::new_work("api-aaa", 7));
+        let other = other.unwrap();
+        assert!(other.work_code.starts_with("api-"));
+        assert_ne!("api-aaa", other.work_code);
+
+        // then a tracking id already taken is drawn again, and still tracks a demand
+        store
+            .create_work(factory::new_work("demand-aaa", 7))
+            .unwrap();
+        let demand = WorkDemand {
+            add_up_to: 7,
+            done: false,
+            tracking_id: Some(String::from("demand-aaa")),
+        };
+        let tracked = store.create_work_retrying(factory::map_to_work(demand, "api"));
+        let tracked = tracked.unwrap();
+        assert_ne!("demand-aaa", tracked.work_code);
+        assert!(factory::is_tracking_id(tracked.work_code.as_str()));
+
+        // then events need an existing work
+        let event = factory::new_event("api-unknown", EventVariable::ComputeStart, None);
+        assert!(store.create_event(event).is_err());
+
+        // then a work with events cannot be deleted, one without can
+        assert!(matches!(
+            store.delete_work(work.id).unwrap_err(),
+            Error::Conflict(_)
+        ));
+        assert!(store.delete_work(other.id).is_ok());
+        assert!(matches!(
+            store.delete_work(other.id).unwrap_err(),
+            Error::NotFound(_)
+        ));
+    }
+
+    #[test]
+    fn test_list_events() {
+        // given the events of two computations
+        let mut store = MemoryStore::new();
+        for work_code in ["w1", "w2"] {
+            store.create_work(factory::new_work(work_code, 5)).unwrap();
+            for variable in [EventVariable::ComputeStart, EventVariable::ComputeStop] {
+                let event = factory::new_event(work_code, variable, None);
+                store.create_event(event).unwrap();
+            }
+        }
+
+        // when listing by work code
+        let events = store
+            .list_events(&EventQuery::new().work_code("w2"))
+            .unwrap();
+        // then
+        let ids: Vec<i32> = events.iter().map(|e| e.id).collect();
+        assert_eq!(vec![3, 4], ids);
+
+        // when listing by variable with paging
+        let query = EventQuery::new()
+            .variable(EventVariable::ComputeStop)
+            .offset(1)
+            .limit(1);
+        let events = store.list_events(&query).unwrap();
+        assert_eq!(1, events.len());
+        assert_eq!("w2", events[0].work_code);
+
+        // when the time window is in the future
+        let query = EventQuery::new().created_between(Some(Utc::now() + Duration::hours(1)), None);
+        assert!(store.list_events(&query).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_clones_share_data_across_threads() {
+        // given a store shared by a few threads
+        let store = MemoryStore::new();
+        let handles: Vec<_> = (0..4)
+            .map(|_| {
+                let mut thread_store = store.clone();
+                thread::spawn(move || {
+                    thread_store
+                        .create_work(factory::generate_random_work("testmemthread"))
+                        .unwrap()
+                })
+            })
+            .collect();
+        let mut ids: Vec<i32> = handles.into_iter().map(|h| h.join().unwrap().id).collect();
+
+        // then every work got its own ID and is visible from the original store
+        ids.sort_unstable();
+        assert_eq!(vec![1, 2, 3, 4], ids);
+        let mut store = store;
+        let query = WorkQuery::new().work_code_prefix("testmemthread");
+        assert_eq!(4, store.search_work(&query).unwrap().len());
+    }
+
+    #[test]
+    fn test_transaction() {
+        // given an in-memory store
+        let mut store = MemoryStore::new();
+
+        // when a unit of work fails midway
+        let res_t = store.in_transaction(&mut |tx| {
+            let work = tx.create_work(factory::new_work("testmemtx-rollback", 5))?;
+            tx.create_event(factory::new_event(
+                work.work_code.as_str(),
+                EventVariable::ComputeStart,
+                None,
+            ))?;
+            tx.update_work_status(-1, WorkStatus::Running)
+        });
+
+        // then its error comes back and nothing is left behind
+        assert!(matches!(res_t.unwrap_err(), Error::NotFound(_)));
+        let query = WorkQuery::new().work_code_prefix("testmemtx");
+        assert!(store.search_work(&query).unwrap().is_empty());
+        assert!(store.list_events(&EventQuery::new()).unwrap().is_empty());
+
+        // when a unit of work succeeds
+        let mut work_id = 0;
+        let res_t = store.in_transaction(&mut |tx| {
+            let work = tx.create_work(factory::new_work("testmemtx-commit", 5))?;
+            tx.create_event(factory::new_event(
+                work.work_code.as_str(),
+                EventVariable::ComputeStart,
+                None,
+            ))?;
+            tx.update_work_status(work.id, WorkStatus::Running)?;
+            work_id = work.id;
+            Ok(())
+        });
+
+        // then all of it is stored
+        assert!(res_t.is_ok());
+        let work = store.retrieve_work(work_id).unwrap();
+        assert_eq!(WorkStatus::Running, work.status);
+        assert_eq!(1, store.list_events(&EventQuery::new()).unwrap().len());
+    }
+
+    #[test]
+    fn test_work_status_transitions() {
+        // given a running work
+        let mut store = MemoryStore::new();
+        let work = store
+            .create_work(factory::generate_random_work("testmemstatus"))
+            .unwrap();
+        assert_eq!(WorkStatus::Pending, work.status);
+        store
+            .update_work_status(work.id, WorkStatus::Queued)
+            .unwrap();
+        store
+            .update_work_status(work.id, WorkStatus::Running)
+            .unwrap();
+
+        // when going back to the queue, then it is refused
+        let res_u = store.update_work_status(work.id, WorkStatus::Queued);
+        assert!(matches!(res_u.unwrap_err(), Error::Conflict(_)));
+
+        // when it fails
+        store
+            .update_work_status(work.id, WorkStatus::Failed)
+            .unwrap();
+
+        // then it can be neither completed nor cancelled
+        let result = factory::new_work_result(42, work.created_on.unwrap());
+        let res_cw = store.complete_work(work.id, &result);
+        assert!(matches!(res_cw.unwrap_err(), Error::Conflict(_)));
+        let res_u = store.update_work_status(work.id, WorkStatus::Cancelled);
+        assert!(matches!(res_u.unwrap_err(), Error::Conflict(_)));
+        let work = store.retrieve_work(work.id).unwrap();
+        assert_eq!(WorkStatus::Failed, work.status);
+        assert_eq!(None, work.result);
+    }
+
+    #[test]
+    fn test_update_and_delete_work() {
+        // given a pending work
+        let mut store = MemoryStore::new();
+        let work = store
+            .create_work(factory::generate_random_work("testmemupd"))
+            .unwrap();
+
+        // when changing its bound and metadata
+        let update = WorkUpdate {
+            add_up_to: Some(7),
+            metadata: Some(Some(json!({"owner": "me"}))),
+            ..WorkUpdate::default()
+        };
+        let updated = store.update_work(work.id, &update).unwrap();
+        // then the other fields stay the same
+        assert_eq!(7, updated.add_up_to);
+        assert_eq!(Some(json!({"owner": "me"})), updated.metadata);
+        assert_eq!(work.work_code, updated.work_code);
+        assert_eq!(updated, store.retrieve_work(work.id).unwrap());
+
+        // when it runs, then its bound cannot change anymore
+        let update = WorkUpdate {
+            status: Some(WorkStatus::Running),
+            ..WorkUpdate::default()
+        };
+        store.update_work(work.id, &update).unwrap();
+        let update = WorkUpdate {
+            add_up_to: Some(8),
+            ..WorkUpdate::default()
+        };
+        let res_u = store.update_work(work.id, &update);
+        assert!(matches!(res_u.unwrap_err(), Error::Conflict(_)));
+
+        // when soft deleting it, then it is gone for the clients
+        store.soft_delete_work(work.id).unwrap();
+        let res_r = store.retrieve_work(work.id);
+        assert!(matches!(res_r.unwrap_err(), Error::NotFound(_)));
+        let res_d = store.soft_delete_work(work.id);
+        assert!(matches!(res_d.unwrap_err(), Error::NotFound(_)));
+        // but its code is still taken
+        let res_c = store.create_work(work.clone());
+        assert!(matches!(res_c.unwrap_err(), Error::Conflict(_)));
+
+        // when hard deleting it, then its code is free again
+        store.delete_work(work.id).unwrap();
+        assert!(store.create_work(work).is_ok());
+    }
+
+    #[test]
+    fn test_retrieve_timeline() {
+        // given a work with a couple of events
+        let mut store = MemoryStore::new();
+        let work = store
+            .create_work(factory::generate_random_work("testmemtl"))
+            .unwrap();
+        for variable in &[EventVariable::ComputeStart, EventVariable::ComputeStop] {
+            let event = factory::new_event(work.work_code.as_str(), *variable, None);
+            store.create_event(event).unwrap();
+        }
+
+        // when retrieving its timeline
+        let timeline = store.retrieve_timeline(work.id).unwrap();
+
+        // then it has the work events, oldest first
+        assert_eq!(work.work_code, timeline.work_code);
+        assert_eq!(2, timeline.events.len());
+        assert_eq!(
+            EventVariable::ComputeStart,
+            timeline.events[0].event.variable
+        );
+        assert!(timeline.compute_duration_ms.unwrap() >= 0);
+
+        // when the work is deleted, then there is no timeline
+        store.soft_delete_work(work.id).unwrap();
+        let res_t = store.retrieve_timeline(work.id);
+        assert!(matches!(res_t.unwrap_err(), Error::NotFound(_)));
+    }
+
+    #[test]
+    fn test_search_work_page() {
+        // given an in-memory store with a few works
+        let mut store = MemoryStore::new();
+        let prefix = format!("testmempage{}", factory::rand_alphanumeric_any(6));
+        let mut ids: Vec<i32> = Vec::new();
+        for add_up_to in 1..=5 {
+            let work = factory::new_work(format!("{}-{}", prefix, add_up_to).as_str(), add_up_to);
+            ids.push(store.create_work(work).unwrap().id);
+        }
+
+        // when following the pages of 2 works
+        let mut query = WorkQuery::new().work_code_prefix(prefix.as_str()).limit(2);
+        let mut pages: Vec<WorkPage> = Vec::new();
+        loop {
+            let page = store.search_work_page(&query).unwrap();
+            let next_cursor = page.next_cursor.clone();
+            pages.push(page);
+            match next_cursor {
+                Some(cursor) => query = query.after(WorkCursor::decode(cursor.as_str()).unwrap()),
+                None => break,
+            }
+        }
+
+        // then every work comes back once, in the creation order
+        assert_eq!(3, pages.len());
+        let paged: Vec<i32> = pages
+            .iter()
+            .flat_map(|p| p.items.iter().map(|w| w.id))
+            .collect();
+        assert_eq!(ids, paged);
+
+        // when paging backward from the last work, then the others come back newest first
+        let last = pages[2].items[0].clone();
+        let query = WorkQuery::new()
+            .work_code_prefix(prefix.as_str())
+            .sort_by(WorkSortField::CreatedOn, SortDirection::Desc)
+            .after(WorkCursor::from_work(&last, WorkSortField::CreatedOn).unwrap());
+        let page = store.search_work_page(&query).unwrap();
+        let backward: Vec<i32> = page.items.iter().map(|w| w.id).collect();
+        assert_eq!(vec![ids[3], ids[2], ids[1], ids[0]], backward);
+        assert_eq!(None, page.next_cursor);
+
+        // when following the pages of 2 works sorted on -add_up_to
+        let mut query = WorkQuery::new()
+            .work_code_prefix(prefix.as_str())
+            .sort_by(WorkSortField::AddUpTo, SortDirection::Desc)
+            .limit(2);
+        let mut paged: Vec<i32> = Vec::new();
+        loop {
+            let page = store.search_work_page(&query).unwrap();
+            paged.extend(page.items.iter().map(|w| w.id));
+            match page.next_cursor {
+                Some(cursor) => query = query.after(WorkCursor::decode(cursor.as_str()).unwrap()),
+                None => break,
+            }
+        }
+        // then every work comes back once, the largest add_up_to first
+        let mut expected = ids.clone();
+        expected.reverse();
+        assert_eq!(expected, paged);
+
+        // when paging on created_on with that cursor, then it is refused
+        let query = query.sort_by(WorkSortField::CreatedOn, SortDirection::Desc);
+        let res_p = store.search_work_page(&query);
+        assert!(matches!(res_p.unwrap_err(), Error::Validation(_)));
+    }
+
+    #[test]
+    fn test_store_pool_shares_data_across_threads() {
+        // given a pool of in-memory stores
+        let pool = StorePool::Memory(MemoryStore::new());
+
+        // when several workers create a work each
+        let workers: Vec<_> = (0..4)
+            .map(|_| {
+                let pool = pool.clone();
+                thread::spawn(move || {
+                    pool.with_store(|store| {
+                        store.create_work(factory::generate_random_work("testmempool"))
+                    })
+                    .unwrap()
+                    .unwrap()
+                })
+            })
+            .collect();
+        for worker in workers {
+            worker.join().unwrap();
+        }
+
+        // then every store of the pool sees all of them
+        let query = WorkQuery::new().work_code_prefix("testmempool");
+        let works = pool.with_store(|store| store.search_work(&query)).unwrap();
+        assert_eq!(4, works.unwrap().len());
+    }
+
+    #[test]
+    fn test_factory_shares_the_memory_store() {
+        // given the in-memory store (no other test of this file reads `PP_STORE`)
+        std::env::set_var("PP_STORE", "memory");
+
+        // when a work is created through one store of the factory
+        let work = factory::work_store()
+            .unwrap()
+            .create_work(factory::generate_random_work("testmemfactory"))
+            .unwrap();
+
+        // then it is found through the other ones
+        let res_r = factory::work_store().unwrap().retrieve_work(work.id);
+        assert_eq!(work, res_r.unwrap());
+        let res_p = factory::work_store_pool(1)
+            .unwrap()
+            .with_store(|store| store.retrieve_work(work.id))
+            .unwrap();
+        assert_eq!(work, res_p.unwrap());
+        // then a short-lived process cannot use it
+        match factory::durable_work_store() {
+            Err(Error::Config(_)) => {}
+            other => panic!("unexpected store: {:?}", other.map(|_| "a store")),
+        }
+    }
+
+    #[test]
+    fn test_work_quota() {
+        // given a store and a daily quota of 2 works
+        let mut store = MemoryStore::new();
+        let today = Utc::now().date_naive();
+
+        // when a client creates works, then it is counted until the quota is reached
+        assert_eq!(1, store.take_work_quota("addr:a", today, 2).unwrap());
+        assert_eq!(2, store.take_work_quota("addr:a", today, 2).unwrap());
+        let res = store.take_work_quota("addr:a", today, 2);
+        assert!(matches!(res, Err(Error::TooManyRequests(_))));
+        // then the other clients and the other days have their own quota
+        assert_eq!(1, store.take_work_quota("addr:b", today, 2).unwrap());
+        let tomorrow = today.succ_opt().unwrap();
+        assert_eq!(1, store.take_work_quota("addr:a", tomorrow, 2).unwrap());
+
+        // when a work is given back, then the client can create another one
+        store.refund_work_quota("addr:a", today).unwrap();
+        assert_eq!(2, store.take_work_quota("addr:a", today, 2).unwrap());
+    }
+}
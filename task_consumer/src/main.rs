@@ -1,87 +1,209 @@
-use std::{thread, time};
+use std::{env, process, thread, time};
 
+use chrono::{Duration as ChronoDuration, Utc};
 use env_logger::Env;
 use job_scheduler::{Job, JobScheduler};
-use log;
-use pp_lib::service::{db, queue};
-use pp_lib::{factory, model};
+use pp_lib::model::{EventValue, EventVariable, WorkStatus};
+use pp_lib::query::WorkQuery;
+use pp_lib::service::queue;
+use pp_lib::service::store::WorkStore;
+use pp_lib::{factory, metrics, model, Error};
 
 static WORK_CODE: &str = "consumer";
-
-fn pull_one_messge() -> model::WorkDemand {
-    let res_sub = queue::consume_amqp_queue(1);
-    let wd_list = res_sub.unwrap();
-    let wd = wd_list[0];
-    wd
+// where Prometheus scrapes `GET /metrics`, unless `PP_METRICS_PORT` says otherwise
+const METRICS_PORT: &str = "9102";
+// how long a step of the calculations takes
+const COMPUTE_STEP_MS: u64 = 100;
+// on top of its calculations, how long a work may stay `Running` before it is given up
+const STALE_GRACE_SECS: i64 = 60;
+
+fn pull_one_messge() -> Result<Option<model::WorkDemand>, Error> {
+    let wd_list = queue::consume_amqp_queue(1)?;
+    Ok(wd_list.into_iter().next())
 }
 
 // TODO figure out strategies with threads and multiple messages
 fn consumer_process(consumer_id: String) {
     log::info!("C-{}: COMMENCE", consumer_id);
     // pull 1 message
-    //
-    // TODO RETURN HERE IF THE QUEUE IS EMPTY!!!
-    // to avoid leaking threads...
-    //
-    let wd: model::WorkDemand = pull_one_messge();
+    let wd: model::WorkDemand = match pull_one_messge() {
+        Ok(Some(wd)) => wd,
+        Ok(None) => {
+            log::info!("C-{}: Nothing to do", consumer_id);
+            return;
+        }
+        Err(err) => {
+            log::error!("C-{}: Could not pull a work demand: {}", consumer_id, err);
+            return;
+        }
+    };
     log::info!("C-{}: Pulled work demand: {:?}", consumer_id, wd);
 
     // map work demand to work
+    let tracked = wd.tracking_id.is_some();
     let w: model::Work = factory::map_to_work(wd, WORK_CODE);
-    let wc_clone = w.work_code.clone();
     log::info!("C-{}: Mapped it to work: {:?}", consumer_id, w);
 
     // TODO DB connection pool: https://github.com/sfackler/r2d2-postgres
-    let mut db = factory::db_client();
-
-    // insert row in table `work`
-    let res_c = db::create_work(&mut db, w.clone());
-
-    // insert row in table `events` to signal: start working
-    let e_c_start = factory::new_event(wc_clone.as_str(), model::VAR_COMPUTE_START, "");
-    let res_ef = db::create_event(&mut db, e_c_start);
+    let mut store = match factory::work_store() {
+        Ok(store) => store,
+        Err(err) => {
+            log::error!("C-{}: Could not connect to the store: {}", consumer_id, err);
+            return;
+        }
+    };
+
+    // insert the `Queued` work (maybe with a new `work_code`) and take it over,
+    // the API already stored the work of a tracked demand: a deleted one is dropped,
+    // a redelivered one fails the transition out of `Queued`
+    let mut stored: Option<model::Work> = None;
+    let res_r = store.in_transaction(&mut |tx| {
+        let queued: model::Work = match tracked {
+            true => tx.find_work_by_code(w.work_code.as_str())?,
+            false => tx.create_work_retrying(w.clone())?,
+        };
+        tx.update_work_status(queued.id, WorkStatus::Running)?;
+        stored = Some(queued);
+        Ok(())
+    });
+    let w: model::Work = match (res_r, stored) {
+        (Ok(_), Some(w)) => w,
+        (Err(err), _) => {
+            log::error!("C-{}: Could not create work: {}", consumer_id, err);
+            return;
+        }
+        (Ok(_), None) => return,
+    };
+
+    metrics::WORKS_IN_FLIGHT.inc();
+
+    // compute first, keeping the events aside with the time they happened at
+    let mut events: Vec<model::Event> = Vec::new();
+    events.push(factory::new_event(
+        w.work_code.as_str(),
+        EventVariable::ComputeStart,
+        None,
+    ));
 
     // do the work demand computation
     log::info!("C-{}: Starting the calculations", consumer_id);
-    let mut total_value = 0;
+    let started_at = factory::now();
+    let mut total_value: i64 = 0;
     for n in 1..w.add_up_to {
         // hard work here...
-        total_value = total_value + n;
-        thread::sleep(time::Duration::from_millis(100));
+        total_value += i64::from(n);
+        thread::sleep(time::Duration::from_millis(COMPUTE_STEP_MS));
     }
     log::info!(
         "C-{}: Done with calculations, result: {:?}",
         consumer_id,
         total_value
     );
+    let result: model::WorkResult = factory::new_work_result(total_value, started_at);
+    metrics::COMPUTE_SECONDS.observe(result.duration_ms() as f64 / 1000.0);
 
-    // insert row in table `events` to signal: stop working
-    let e_c_stop = factory::new_event(wc_clone.as_str(), model::VAR_COMPUTE_STOP, "");
-    let res_ef = db::create_event(&mut db, e_c_stop);
+    events.push(factory::new_event(
+        w.work_code.as_str(),
+        EventVariable::ComputeStop,
+        None,
+    ));
+    events.push(factory::new_event(
+        w.work_code.as_str(),
+        EventVariable::ComputeResult,
+        Some(EventValue::Integer(total_value)),
+    ));
 
-    // update `work` with done=true (`updated_on` field as well...)
-    let res_u = db::update_work_done(&mut db, w.id);
+    // then persist the outcome as one unit of work: the events and the `Succeeded` work
+    let res_t = store.in_transaction(&mut |tx| {
+        for event in events.iter() {
+            tx.create_event(event.clone())?;
+        }
+        tx.complete_work(w.id, &result)
+    });
+
+    // nothing of the outcome has been stored, mark the work as `Failed` instead
+    if let Err(err) = res_t {
+        log::error!("C-{}: Could not store the work: {}", consumer_id, err);
+        let res_f = store.in_transaction(&mut |tx| {
+            tx.update_work_status(w.id, WorkStatus::Failed)?;
+            tx.create_event(factory::new_event(
+                w.work_code.as_str(),
+                EventVariable::ComputeFailure,
+                Some(EventValue::Text(err.to_string())),
+            ))
+        });
+        if let Err(err) = res_f {
+            log::error!("C-{}: Could not report the failure: {}", consumer_id, err);
+        }
+    }
 
-    // insert row in table `events` to signal: computation outcome
-    let e_cr = factory::new_event(
-        wc_clone.as_str(),
-        model::VAR_COMPUTE_RESULT,
-        format!("{}", total_value).as_str(),
-    );
-    let res_ef = db::create_event(&mut db, e_cr);
+    metrics::WORKS_IN_FLIGHT.dec();
 
-    // close DB connection
-    let res_db_c = db.close();
-    assert!(res_db_c.is_ok());
+    // the DB connection is closed when the store is dropped
+    drop(store);
     log::info!("C-{}: DONE", consumer_id);
 }
 
+/// Fail the works left `Running` by a consumer which crashed, once they have been running
+/// for longer than their calculations take: the demand is gone from the queue, nobody resumes them.
+fn fail_stale_works(store: &mut dyn WorkStore) -> Result<usize, Error> {
+    let now = Utc::now();
+    let stale = |work: &model::Work| {
+        let compute =
+            ChronoDuration::milliseconds(i64::from(work.add_up_to) * COMPUTE_STEP_MS as i64);
+        match work.updated_on {
+            Some(running_since) => {
+                running_since + compute + ChronoDuration::seconds(STALE_GRACE_SECS) < now
+            }
+            None => true,
+        }
+    };
+    let running = store.search_work(&WorkQuery::new().status(WorkStatus::Running))?;
+    let mut failed = 0;
+    for work in running.iter().filter(|work| stale(work)) {
+        let res_f = store.in_transaction(&mut |tx| {
+            tx.update_work_status(work.id, WorkStatus::Failed)?;
+            tx.create_event(factory::new_event(
+                work.work_code.as_str(),
+                EventVariable::ComputeFailure,
+                Some(EventValue::Text(String::from("Abandoned while running"))),
+            ))
+        });
+        match res_f {
+            Ok(_) => failed += 1,
+            // e.g. completed meanwhile
+            Err(err) => log::warn!("Could not fail the stale work {}: {}", work.work_code, err),
+        }
+    }
+    Ok(failed)
+}
+
 fn main() {
     let env = Env::default()
         .filter_or("MY_LOG_LEVEL", "info")
         .write_style_or("MY_LOG_STYLE", "always");
     env_logger::init_from_env(env);
 
+    // fail fast instead of failing every job on a missing table or column,
+    // then give up the works a previous run left behind
+    let recovered = factory::work_store().and_then(|mut store| {
+        store.check_schema()?;
+        fail_stale_works(store.as_mut())
+    });
+    match recovered {
+        Ok(failed) => log::info!("Failed {} works left running", failed),
+        Err(err) => {
+            log::error!("Not able to start: {}", err);
+            process::exit(1);
+        }
+    }
+
+    let metrics_port = env::var("PP_METRICS_PORT").unwrap_or_else(|_| String::from(METRICS_PORT));
+    if let Err(err) = metrics::serve(metrics_port.as_str()) {
+        log::error!("Not able to start: {}", err);
+        process::exit(1);
+    }
+
     let n_seconds: u8 = 4; // within 1 minute: 60 seconds
     let task_schedule = format!("1/{} * * * * *", n_seconds);
 
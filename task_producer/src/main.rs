@@ -1,9 +1,12 @@
 use env_logger::Env;
 use job_scheduler::{Job, JobScheduler};
-use log;
-use pp_lib::factory;
 use pp_lib::service::queue;
+use pp_lib::{factory, metrics};
 use std::time::Duration;
+use std::{env, process};
+
+// where Prometheus scrapes `GET /metrics`, unless `PP_METRICS_PORT` says otherwise
+const METRICS_PORT: &str = "9101";
 
 fn main() {
     let env = Env::default()
@@ -11,6 +14,12 @@ fn main() {
         .write_style_or("MY_LOG_STYLE", "always");
     env_logger::init_from_env(env);
 
+    let metrics_port = env::var("PP_METRICS_PORT").unwrap_or_else(|_| String::from(METRICS_PORT));
+    if let Err(err) = metrics::serve(metrics_port.as_str()) {
+        log::error!("Not able to start: {}", err);
+        process::exit(1);
+    }
+
     let n_seconds: u8 = 4; // within 1 minute: 60 seconds
     let task_schedule = format!("1/{} * * * * *", n_seconds);
 
@@ -19,11 +28,9 @@ fn main() {
     sched.add(Job::new(task_schedule.as_str().parse().unwrap(), || {
         let wd = factory::generate_random_work_demand();
         log::info!("Generated this work to do: {:?}", wd);
-        let res_pub = queue::publish(&wd);
-        if res_pub.is_ok() {
-            log::info!("Publish this work to do: {:?}", wd);
-        } else {
-            log::error!("Did NOT publish work to do: {:?}", wd);
+        match queue::publish(&wd) {
+            Ok(_) => log::info!("Publish this work to do: {:?}", wd),
+            Err(err) => log::error!("Did NOT publish work to do: {:?}, error: {}", wd, err),
         }
     }));
 